@@ -2,12 +2,44 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-use crate::commands::{AddCommand, Command, InitCommand, RemoveCommand, SyncCommand};
+use crate::commands::{
+    AddCommand, AddOptions, Command, EjectCommand, InitCommand, LinkCommand, LinkOptions,
+    MigrateCommand, MoveCommand, MoveOptions, RemoveCommand, RenderCommand, SecretEditCommand,
+    SecretKeygenCommand, StatusCommand, SyncCommand, SyncOptions, UnlinkCommand, UnlinkOptions,
+};
+use crate::conflict::ConflictStrategy;
+use crate::context::Context;
 use crate::error::Result;
+use crate::path::to_lexical_absolute;
+use crate::repo;
 
 #[derive(Parser)]
 #[command(version, about = "A simple dotfiles manager")]
 pub struct Cli {
+    /// Path to the dot repository (defaults to searching upwards for dot.toml)
+    #[arg(long, global = true, env = "DOT_REPO")]
+    repo: Option<PathBuf>,
+
+    /// Directory that `~` in the manifest refers to (defaults to your home directory)
+    #[arg(long, global = true)]
+    home: Option<PathBuf>,
+
+    /// Deploy every target under this directory instead of `/`
+    #[arg(long, global = true)]
+    target_root: Option<PathBuf>,
+
+    /// Print the changes a command would make without applying them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Only deploy entries in this profile (defaults to profiles matching the hostname)
+    #[arg(long, global = true, env = "DOT_PROFILE", value_delimiter = ',')]
+    profile: Vec<String>,
+
+    /// Age key file for encrypted entries (defaults to a passphrase from DOT_PASSPHRASE or a prompt)
+    #[arg(long, global = true, env = "DOT_KEY_FILE")]
+    key_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: CliCommand,
 }
@@ -16,22 +48,156 @@ pub struct Cli {
 enum CliCommand {
     /// Initialize a new dot repository
     Init,
-    /// Track a file by moving it here and creating a symlink
-    Add { path: PathBuf },
+    /// Track files or directories by moving them here and creating symlinks
+    Add {
+        /// Files, directories or quoted glob patterns to track together
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Store the file at this path inside the repository
+        #[arg(long = "as", value_name = "REPO_PATH")]
+        repo_path: Option<PathBuf>,
+        /// Keep a directory in place and link each of its files instead
+        #[arg(long)]
+        unfold: bool,
+        /// Only track files of an unfolded directory matching this glob
+        #[arg(long, value_name = "GLOB", requires = "unfold")]
+        include: Vec<String>,
+        /// Leave files of an unfolded directory matching this glob untracked
+        #[arg(long, value_name = "GLOB", requires = "unfold")]
+        exclude: Vec<String>,
+        /// Store the file encrypted and keep the plaintext in place
+        #[arg(long, conflicts_with = "unfold")]
+        encrypt: bool,
+    },
+    /// Track a file that is already in the repository and link it to a target
+    Link {
+        /// File inside the repository
+        path: PathBuf,
+        /// Where to deploy it
+        target: PathBuf,
+        /// How to handle a file that already exists at the target (refuses by default)
+        #[arg(long, value_enum)]
+        on_conflict: Option<ConflictStrategy>,
+    },
     /// Stop tracking a file and restore it
     Remove { path: PathBuf },
+    /// Rename a file in the repository or change where it is deployed
+    Mv {
+        path: PathBuf,
+        /// New path inside the repository
+        #[arg(value_name = "REPO_PATH", required_unless_present = "target")]
+        repo_path: Option<PathBuf>,
+        /// Deploy the entry here instead
+        #[arg(long, value_name = "PATH")]
+        target: Option<PathBuf>,
+    },
     /// Create symlinks for all tracked files
-    Sync,
+    Sync {
+        /// Replace symlinks that point somewhere other than the repository file
+        #[arg(long)]
+        relink: bool,
+        /// How to handle regular files that already exist at a target
+        #[arg(long, value_enum, default_value_t)]
+        on_conflict: ConflictStrategy,
+    },
+    /// Show the link state of every tracked file
+    Status,
+    /// Remove every deployed link, leaving the repository untouched
+    Unlink {
+        /// Also remove copied, rendered and decrypted files
+        #[arg(long)]
+        all: bool,
+    },
+    /// Stop tracking every deployed file and restore it
+    Eject,
+    /// Print what a template entry renders to on this machine
+    Render { path: PathBuf },
+    /// Rewrite dot.toml in the current manifest format
+    Migrate,
+    /// Manage encrypted entries
+    Secret {
+        #[command(subcommand)]
+        command: SecretCliCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretCliCommand {
+    /// Decrypt an encrypted entry, open it in $EDITOR and re-encrypt it
+    Edit { path: PathBuf },
+    /// Create a new key file to use with --key-file
+    Keygen { path: PathBuf },
 }
 
 pub fn run() -> Result<()> {
     let cli = Cli::parse();
+    let cwd = std::env::current_dir()?;
+    let repo = match cli.command {
+        // A new repository is created where asked, without searching
+        CliCommand::Init => to_lexical_absolute(&cli.repo.unwrap_or(cwd))?,
+        _ => repo::discover(cli.repo.as_deref(), &cwd)?,
+    };
+    let mut ctx = Context::new(repo);
+    ctx.dry_run = cli.dry_run;
+    ctx.profiles = cli.profile;
+    ctx.key_file = cli.key_file;
+    if let Some(home) = &cli.home {
+        ctx.home = Some(to_lexical_absolute(home)?);
+    }
+    ctx.target_root = cli
+        .target_root
+        .as_deref()
+        .map(to_lexical_absolute)
+        .transpose()?;
 
     match cli.command {
-        CliCommand::Init => InitCommand::new().execute(),
-        CliCommand::Add { path } => AddCommand::new(path).execute(),
-        CliCommand::Remove { path } => RemoveCommand::new(path).execute(),
-        CliCommand::Sync => SyncCommand::new().execute(),
+        CliCommand::Init => InitCommand::new().execute(&ctx),
+        CliCommand::Add {
+            paths,
+            repo_path,
+            unfold,
+            include,
+            exclude,
+            encrypt,
+        } => AddCommand::new(
+            paths,
+            AddOptions {
+                repo_path,
+                unfold,
+                include,
+                exclude,
+                encrypt,
+            },
+        )
+        .execute(&ctx),
+        CliCommand::Link {
+            path,
+            target,
+            on_conflict,
+        } => LinkCommand::new(path, target, LinkOptions { on_conflict }).execute(&ctx),
+        CliCommand::Remove { path } => RemoveCommand::new(path).execute(&ctx),
+        CliCommand::Mv {
+            path,
+            repo_path,
+            target,
+        } => MoveCommand::new(path, MoveOptions { repo_path, target }).execute(&ctx),
+        CliCommand::Sync {
+            relink,
+            on_conflict,
+        } => SyncCommand::new(SyncOptions {
+            relink,
+            on_conflict,
+        })
+        .execute(&ctx),
+        CliCommand::Status => StatusCommand::new().execute(&ctx),
+        CliCommand::Unlink { all } => UnlinkCommand::new(UnlinkOptions { all }).execute(&ctx),
+        CliCommand::Eject => EjectCommand::new().execute(&ctx),
+        CliCommand::Render { path } => RenderCommand::new(path).execute(&ctx),
+        CliCommand::Migrate => MigrateCommand::new().execute(&ctx),
+        CliCommand::Secret { command } => match command {
+            SecretCliCommand::Edit { path } => SecretEditCommand::new(path).execute(&ctx),
+            SecretCliCommand::Keygen { path } => SecretKeygenCommand::new(path).execute(&ctx),
+        },
     }
 }
 
@@ -39,6 +205,7 @@ pub fn run() -> Result<()> {
 mod tests {
     use super::*;
     use clap::CommandFactory;
+    use std::path::Path;
 
     #[test]
     fn verify_cli() {
@@ -54,23 +221,228 @@ mod tests {
     #[test]
     fn parse_add() {
         let cli = Cli::try_parse_from(["dot", "add", "/path/file"]).unwrap();
-        assert!(
-            matches!(cli.command, CliCommand::Add { path } if path == PathBuf::from("/path/file"))
-        );
+        assert!(matches!(
+            cli.command,
+            CliCommand::Add { paths, repo_path: None, unfold: false, .. }
+                if paths == [Path::new("/path/file")]
+        ));
+    }
+
+    #[test]
+    fn parse_add_many() {
+        let cli = Cli::try_parse_from(["dot", "add", "/a", "/b/*"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Add { paths, .. } if paths.len() == 2
+        ));
+        assert!(Cli::try_parse_from(["dot", "add"]).is_err());
+    }
+
+    #[test]
+    fn parse_add_as() {
+        let cli = Cli::try_parse_from(["dot", "add", "/path/file", "--as", "nvim/file"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Add { repo_path: Some(p), .. } if p == Path::new("nvim/file")
+        ));
+    }
+
+    #[test]
+    fn parse_add_unfold_with_globs() {
+        let cli = Cli::try_parse_from([
+            "dot",
+            "add",
+            "/path/fish",
+            "--unfold",
+            "--include",
+            "*.fish",
+            "--exclude",
+            "fish_variables",
+            "--exclude",
+            "completions/*",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Add { unfold: true, include, exclude, .. }
+                if include == ["*.fish"] && exclude == ["fish_variables", "completions/*"]
+        ));
+    }
+
+    #[test]
+    fn globs_require_unfold() {
+        assert!(Cli::try_parse_from(["dot", "add", "/path/fish", "--exclude", "x"]).is_err());
     }
 
     #[test]
     fn parse_remove() {
         let cli = Cli::try_parse_from(["dot", "remove", "myfile"]).unwrap();
-        assert!(
-            matches!(cli.command, CliCommand::Remove { path } if path == PathBuf::from("myfile"))
-        );
+        assert!(matches!(cli.command, CliCommand::Remove { path } if path == Path::new("myfile")));
     }
 
     #[test]
     fn parse_sync() {
         let cli = Cli::try_parse_from(["dot", "sync"]).unwrap();
-        assert!(matches!(cli.command, CliCommand::Sync));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Sync {
+                relink: false,
+                on_conflict: ConflictStrategy::Skip
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_sync_relink() {
+        let cli = Cli::try_parse_from(["dot", "sync", "--relink"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Sync { relink: true, .. }));
+    }
+
+    #[test]
+    fn parse_sync_on_conflict() {
+        let cli = Cli::try_parse_from(["dot", "sync", "--on-conflict", "backup"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Sync {
+                on_conflict: ConflictStrategy::Backup,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_status() {
+        let cli = Cli::try_parse_from(["dot", "status"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Status));
+    }
+
+    #[test]
+    fn parse_link() {
+        let cli = Cli::try_parse_from(["dot", "link", "vim/vimrc", "~/.vimrc"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Link {
+                on_conflict: None,
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from([
+            "dot",
+            "link",
+            "vim/vimrc",
+            "~/.vimrc",
+            "--on-conflict",
+            "backup",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Link {
+                on_conflict: Some(ConflictStrategy::Backup),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_mv() {
+        let cli = Cli::try_parse_from(["dot", "mv", "vimrc", "vim/vimrc"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Mv { repo_path: Some(p), target: None, .. } if p == Path::new("vim/vimrc")
+        ));
+        let cli = Cli::try_parse_from(["dot", "mv", "~/.vimrc", "--target", "~/.config/vim/vimrc"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            CliCommand::Mv {
+                repo_path: None,
+                target: Some(_),
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["dot", "mv", "vimrc"]).is_err());
+    }
+
+    #[test]
+    fn parse_unlink_and_eject() {
+        let cli = Cli::try_parse_from(["dot", "unlink", "--dry-run"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Unlink { all: false }));
+        assert!(cli.dry_run);
+        let cli = Cli::try_parse_from(["dot", "unlink", "--all"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Unlink { all: true }));
+        let cli = Cli::try_parse_from(["dot", "eject"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Eject));
+    }
+
+    #[test]
+    fn parse_render() {
+        let cli = Cli::try_parse_from(["dot", "render", "gitconfig"]).unwrap();
+        assert!(
+            matches!(cli.command, CliCommand::Render { path } if path == Path::new("gitconfig"))
+        );
+    }
+
+    #[test]
+    fn parse_add_encrypt() {
+        let cli = Cli::try_parse_from(["dot", "add", "/path/netrc", "--encrypt"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Add { encrypt: true, .. }));
+        assert!(Cli::try_parse_from(["dot", "add", "/path/dir", "--encrypt", "--unfold"]).is_err());
+    }
+
+    #[test]
+    fn parse_secret_edit() {
+        let cli =
+            Cli::try_parse_from(["dot", "--key-file", "/k", "secret", "edit", "netrc"]).unwrap();
+        assert_eq!(cli.key_file.as_deref(), Some(Path::new("/k")));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Secret { command: SecretCliCommand::Edit { path } } if path == Path::new("netrc")
+        ));
+    }
+
+    #[test]
+    fn parse_migrate() {
+        let cli = Cli::try_parse_from(["dot", "migrate"]).unwrap();
+        assert!(matches!(cli.command, CliCommand::Migrate));
+    }
+
+    #[test]
+    fn parse_global_dry_run() {
+        let cli = Cli::try_parse_from(["dot", "sync", "--dry-run"]).unwrap();
+        assert!(cli.dry_run);
+        let cli = Cli::try_parse_from(["dot", "--dry-run", "add", "file"]).unwrap();
+        assert!(cli.dry_run);
+    }
+
+    #[test]
+    fn parse_profiles() {
+        let cli = Cli::try_parse_from(["dot", "sync", "--profile", "server,gui"]).unwrap();
+        assert_eq!(cli.profile, ["server", "gui"]);
+        let cli = Cli::try_parse_from(["dot", "--profile", "server", "--profile", "gui", "status"])
+            .unwrap();
+        assert_eq!(cli.profile, ["server", "gui"]);
+    }
+
+    #[test]
+    fn parse_global_repo() {
+        let cli = Cli::try_parse_from(["dot", "status", "--repo", "/dotfiles"]).unwrap();
+        assert_eq!(cli.repo.as_deref(), Some(Path::new("/dotfiles")));
+    }
+
+    #[test]
+    fn parse_home_and_target_root() {
+        let cli = Cli::try_parse_from([
+            "dot",
+            "sync",
+            "--home",
+            "/home/ci",
+            "--target-root",
+            "/stage",
+        ])
+        .unwrap();
+        assert_eq!(cli.home.as_deref(), Some(Path::new("/home/ci")));
+        assert_eq!(cli.target_root.as_deref(), Some(Path::new("/stage")));
     }
 
     #[test]
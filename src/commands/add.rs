@@ -1,52 +1,375 @@
 use std::fs;
-use std::os::unix::fs::symlink;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
-use crate::commands::Command;
+use crate::commands::{Command, save_manifest_and_state};
+use crate::context::Context;
+use crate::entry::{DirMode, Entry, LinkMode};
 use crate::error::{Error, Result};
+use crate::link::symlink_source;
 use crate::manifest::Manifest;
+use crate::path::{is_repo_relative, repo_path_for, to_lexical_absolute};
+use crate::plan::{Action, Plan};
+use crate::state::{State, content_hash};
 
 pub struct AddCommand {
-    file_path: PathBuf,
+    file_paths: Vec<PathBuf>,
+    options: AddOptions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AddOptions {
+    /// Store the file at this path inside the repository
+    pub repo_path: Option<PathBuf>,
+    /// Link each file of a directory individually instead of the directory
+    pub unfold: bool,
+    /// Only track files of an unfolded directory that match these globs
+    pub include: Vec<String>,
+    /// Leave files of an unfolded directory that match these globs in place
+    pub exclude: Vec<String>,
+    /// Store the file encrypted and leave the plaintext at its target
+    pub encrypt: bool,
 }
 
 impl AddCommand {
-    pub fn new(file_path: PathBuf) -> Self {
-        Self { file_path }
+    pub fn new(file_paths: Vec<PathBuf>, options: AddOptions) -> Self {
+        Self {
+            file_paths,
+            options,
+        }
     }
 
-    /// Core logic separated for testing with custom manifest
-    pub fn add_to_manifest(manifest: &mut Manifest, file_path: &Path) -> Result<PathBuf> {
-        let file_name = file_path
-            .file_name()
-            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
-        let local_path = Path::new(file_name);
+    /// The manifest entry that tracks `file_path` with `options`.
+    fn entry_for(
+        manifest: &Manifest,
+        file_path: &Path,
+        options: &AddOptions,
+        ctx: &Context,
+    ) -> Result<Entry> {
+        let mut entry = Entry::new(ctx.collapse(file_path)?);
+        let metadata = file_path.symlink_metadata()?;
+        let is_dir = metadata.is_dir();
+
+        if options.unfold {
+            if !is_dir {
+                return Err(Error::NotADirectory(file_path.to_path_buf()));
+            }
+            entry.dir = Some(DirMode::Unfold);
+            entry.include = options.include.clone();
+            entry.exclude = options.exclude.clone();
+            entry.validate()?;
+        } else if options.encrypt {
+            if !file_path.is_file() {
+                return Err(Error::NotAFile(file_path.to_path_buf()));
+            }
+            entry.mode = LinkMode::Encrypted;
+        } else if is_dir && manifest.version() > 1 {
+            // Folding is the default, so version 1 manifests go without
+            entry.dir = Some(DirMode::Fold);
+        }
+
+        // Version 1 manifests cannot hold permissions, and decrypted
+        // secrets are always private whatever the plaintext's mode was
+        if manifest.version() > 1 && entry.mode != LinkMode::Encrypted {
+            entry.permissions = if entry.is_unfolded() {
+                Self::shared_mode(file_path, &entry)?
+            } else {
+                Some(metadata.permissions().mode() & 0o7777)
+            };
+        }
+        Ok(entry)
+    }
+
+    /// The permission bits every file an unfolded `entry` selects below
+    /// `dir` has, or `None` if they differ.
+    fn shared_mode(dir: &Path, entry: &Entry) -> Result<Option<u32>> {
+        let mut shared = None;
+        for relative in entry.selected_files(dir)? {
+            let mode = dir.join(relative).symlink_metadata()?.permissions().mode() & 0o7777;
+            if shared.is_some_and(|shared| shared != mode) {
+                return Ok(None);
+            }
+            shared = Some(mode);
+        }
+        Ok(shared)
+    }
+
+    /// Work out the filesystem changes needed to track `file_path`.
+    ///
+    /// The file is stored at `options.repo_path` if given, otherwise at a
+    /// path that mirrors its target (see `repo_path_for`). An unfolded
+    /// directory stays in place and only its selected files are moved. An
+    /// encrypted file stays in place too, and only its ciphertext is stored.
+    pub fn plan_add(
+        manifest: &Manifest,
+        file_path: &Path,
+        options: &AddOptions,
+        ctx: &Context,
+    ) -> Result<(PathBuf, Plan)> {
+        let Ok(metadata) = file_path.symlink_metadata() else {
+            return Err(Error::NotFound(file_path.to_path_buf()));
+        };
+        if file_path.file_name().is_none() {
+            return Err(Error::NotFound(file_path.to_path_buf()));
+        }
+        let file_type = metadata.file_type();
+        if file_type.is_fifo()
+            || file_type.is_socket()
+            || file_type.is_block_device()
+            || file_type.is_char_device()
+        {
+            return Err(Error::SpecialFile(file_path.to_path_buf()));
+        }
+        // Already deployed by another entry, e.g. a symlink into the repository
+        let absolute = to_lexical_absolute(file_path)?;
+        for (_, target) in ctx.targets(manifest) {
+            if target? == absolute {
+                return Err(Error::AlreadyTracked(file_path.to_path_buf()));
+            }
+        }
+
+        let local_path = match &options.repo_path {
+            Some(path) if !is_repo_relative(path) => {
+                return Err(Error::InvalidRepoPath(path.to_path_buf()));
+            }
+            Some(path) => path.to_path_buf(),
+            None => repo_path_for(&ctx.collapse(file_path)?),
+        };
+
+        if manifest.contains(&local_path) {
+            return Err(Error::AlreadyTracked(local_path));
+        }
+
+        let local = ctx.local(&local_path);
+        if local.symlink_metadata().is_ok() {
+            return Err(Error::AlreadyExists(local));
+        }
+
+        let entry = Self::entry_for(manifest, file_path, options, ctx)?;
+        let relative_links = manifest.is_relative(&entry);
+        let mut plan = Plan::new();
 
-        if manifest.contains(local_path) {
-            return Err(Error::AlreadyTracked(local_path.to_path_buf()));
+        if entry.is_unfolded() {
+            plan.ensure_dir(&local);
+            for relative in entry.selected_files(file_path)? {
+                let from = file_path.join(&relative);
+                let to = local.join(&relative);
+                if let Some(parent) = to.parent() {
+                    plan.ensure_dir(parent);
+                }
+                plan.push(Action::Move {
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+                plan.push(Action::Symlink {
+                    original: symlink_source(&to, &from, relative_links)?,
+                    link: from,
+                });
+            }
+            return Ok((local_path, plan));
         }
 
-        // Move file to current directory
-        fs::rename(file_path, local_path)?;
+        // Mirror the target's directories inside the repository
+        if let Some(parent) = local.parent() {
+            plan.ensure_dir(parent);
+        }
+
+        if entry.mode == LinkMode::Encrypted {
+            let ciphertext = ctx.key()?.encrypt(&fs::read(file_path)?)?;
+            plan.push(Action::Write {
+                path: local,
+                content: ciphertext.into_bytes(),
+                mode: None,
+            });
+            // The plaintext left in place is the deployed secret now
+            let mode = file_path.metadata()?.permissions().mode() & 0o7777;
+            if let Some(private) = entry.deployed_permissions()
+                && mode != private
+            {
+                plan.push(Action::SetPermissions {
+                    path: file_path.to_path_buf(),
+                    mode: private,
+                });
+            }
+            return Ok((local_path, plan));
+        }
+
+        // Move file into the repository
+        plan.push(Action::Move {
+            from: file_path.to_path_buf(),
+            to: local.clone(),
+        });
 
         // Create symlink at original location
-        let canonical = local_path.canonicalize()?;
-        symlink(&canonical, file_path)?;
+        plan.push(Action::Symlink {
+            original: symlink_source(&local, file_path, relative_links)?,
+            link: file_path.to_path_buf(),
+        });
+
+        Ok((local_path, plan))
+    }
+
+    /// Track a single path, as tests mostly do.
+    #[cfg(test)]
+    pub fn add_to_manifest(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        options: &AddOptions,
+        ctx: &Context,
+    ) -> Result<PathBuf> {
+        let mut added =
+            Self::add_all_to_manifest(manifest, &[file_path.to_path_buf()], options, ctx)?;
+        Ok(added.remove(0).0)
+    }
+
+    /// Track every path in `file_paths` in a single transaction, expanding
+    /// glob patterns first. Each repository path is returned with the file
+    /// it came from.
+    ///
+    /// All paths are checked before anything is moved, and if any step
+    /// fails every file is put back and the manifest is saved unchanged.
+    pub fn add_all_to_manifest(
+        manifest: &mut Manifest,
+        file_paths: &[PathBuf],
+        options: &AddOptions,
+        ctx: &Context,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        Self::add_all_to_manifest_with(manifest, file_paths, options, ctx, |_| Ok(()))
+    }
+
+    /// Like `add_all_to_manifest`, running `check` before each filesystem
+    /// step (see `Plan::begin_with`).
+    fn add_all_to_manifest_with(
+        manifest: &mut Manifest,
+        file_paths: &[PathBuf],
+        options: &AddOptions,
+        ctx: &Context,
+        check: impl FnMut(usize) -> Result<()>,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let files = Self::expand_paths(file_paths, ctx)?;
+        if options.repo_path.is_some() && files.len() > 1 {
+            return Err(Error::RepoPathForMany);
+        }
+
+        let mut updated = manifest.clone();
+        let mut plan = Plan::new();
+        let mut added: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let mut seen = Vec::new();
+        for file_path in files {
+            let absolute = to_lexical_absolute(&file_path)?;
+            if seen.contains(&absolute) {
+                return Err(Error::DuplicatePath(file_path));
+            }
+            seen.push(absolute);
+
+            let (local_path, file_plan) = Self::plan_add(manifest, &file_path, options, ctx)?;
+            // Two files stored at the same path, or one inside the other
+            if let Some((_, other_file)) = added
+                .iter()
+                .find(|(other, _)| other.starts_with(&local_path) || local_path.starts_with(other))
+            {
+                return Err(Error::RepoPathCollision(
+                    other_file.clone(),
+                    file_path,
+                    local_path,
+                ));
+            }
+
+            // Update the manifest first so an unresolvable target (e.g. no
+            // home directory) fails before anything on disk has changed
+            updated.insert_entry(
+                local_path.clone(),
+                Self::entry_for(manifest, &file_path, options, ctx)?,
+            );
+            plan.append(file_plan);
+            added.push((local_path, file_path));
+        }
+
+        let transaction = ctx.begin_with(&plan, check)?;
+        if !ctx.dry_run
+            && let Err(e) = Self::record_state(&updated, &added, &plan, ctx)
+                .and_then(|state| save_manifest_and_state(&updated, manifest, &state, ctx))
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
+        *manifest = updated;
+        Ok(added)
+    }
+
+    /// Replace each glob pattern in `file_paths` with the paths it matches,
+    /// in order. A path that exists is taken literally even if it looks like
+    /// a pattern, and a quoted `~` is expanded like a manifest target.
+    fn expand_paths(file_paths: &[PathBuf], ctx: &Context) -> Result<Vec<PathBuf>> {
+        let mut expanded = Vec::new();
+        for file_path in file_paths {
+            let file_path = if file_path.starts_with("~") {
+                ctx.expand(file_path)?
+            } else {
+                file_path.clone()
+            };
+            let pattern = file_path.to_string_lossy();
+            if file_path.symlink_metadata().is_ok() || !pattern.contains(['*', '?', '[']) {
+                expanded.push(file_path);
+                continue;
+            }
+
+            let matches = glob::glob(&pattern)
+                .map_err(|e| Error::InvalidGlob(pattern.to_string(), e.msg.to_string()))?;
+            let before = expanded.len();
+            for path in matches {
+                expanded.push(path.map_err(|e| Error::Io(e.into()))?);
+            }
+            if expanded.len() == before {
+                return Err(Error::NotFound(file_path));
+            }
+        }
+        Ok(expanded)
+    }
 
-        // Update manifest
-        manifest.insert(local_path.to_path_buf(), file_path)?;
+    /// Record the symlinks the plan creates, so sync can prune them once
+    /// the entry is gone, and encrypted files as deployed, so status knows
+    /// the plaintext left at their targets is current.
+    fn record_state(
+        manifest: &Manifest,
+        added: &[(PathBuf, PathBuf)],
+        plan: &Plan,
+        ctx: &Context,
+    ) -> Result<State> {
+        let mut state = State::load_from(&ctx.state_path())?;
+        for action in plan.actions() {
+            if let Action::Symlink { link, .. } = action {
+                state.record_link(link.clone());
+            }
+        }
 
-        Ok(local_path.to_path_buf())
+        for (local_path, file_path) in added {
+            if manifest.entry(local_path).map(|e| e.mode) != Some(LinkMode::Encrypted) {
+                continue;
+            }
+            let target = ctx
+                .target(manifest, local_path)
+                .ok_or_else(|| Error::NotFound(local_path.to_path_buf()))?;
+            state.record_copy(target.clone(), content_hash(file_path)?);
+            state.record_source(target, content_hash(&ctx.local(local_path))?);
+        }
+        Ok(state)
     }
 }
 
 impl Command for AddCommand {
-    fn execute(self) -> Result<()> {
-        let mut manifest = Manifest::load()?;
-        let local_path = Self::add_to_manifest(&mut manifest, &self.file_path)?;
-        manifest.save()?;
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let mut manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let added = Self::add_all_to_manifest(&mut manifest, &self.file_paths, &self.options, ctx)?;
+
+        if ctx.dry_run {
+            return Ok(());
+        }
 
-        println!("{} -> {}", local_path.display(), self.file_path.display());
+        for (local_path, file_path) in &added {
+            println!("{} -> {}", local_path.display(), file_path.display());
+        }
         Ok(())
     }
 }
@@ -54,49 +377,67 @@ impl Command for AddCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::TempDir;
-
-    /// Test helper that adds a file using absolute paths
-    fn add_file(source_file: &Path, dest_dir: &Path) -> Result<PathBuf> {
-        let file_name = source_file
-            .file_name()
-            .ok_or_else(|| Error::NotFound(source_file.to_path_buf()))?;
-        let local_path = dest_dir.join(file_name);
-
-        // Move file to dest directory
-        fs::rename(source_file, &local_path)?;
-
-        // Create symlink at original location
-        let canonical = local_path.canonicalize()?;
-        symlink(&canonical, source_file)?;
-
-        Ok(local_path)
-    }
+    use crate::commands::{SyncCommand, SyncOptions};
+    use crate::link::LinkState;
+    use crate::manifest::MANIFEST_FILE;
+    use crate::plan;
+    use crate::secret::Key;
+    use crate::testing::Fixture;
+    use std::rc::Rc;
 
     #[test]
     fn rejects_already_tracked_file() {
+        let f = Fixture::new();
+        let source_file = f.source("testfile");
+
         let mut manifest = Manifest::empty();
         manifest
             .insert("testfile".into(), Path::new("/some/path"))
             .unwrap();
 
-        // add_to_manifest checks manifest.contains() with just the filename
-        // So if manifest already has "testfile", adding any file named "testfile" should fail
-        assert!(manifest.contains(Path::new("testfile")));
+        let result = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::AlreadyTracked(_))));
+    }
+
+    #[test]
+    fn rejects_target_of_another_entry() {
+        let f = Fixture::new();
+        let source_file = f.source(".vimrc");
+        let mut manifest = Manifest::empty();
+        manifest.insert_entry("vim/vimrc".into(), Entry::new("~/.vimrc".into()));
+
+        let result = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::AlreadyTracked(_))));
     }
 
     #[test]
     fn moves_file_and_creates_symlink() {
-        let repo = TempDir::new().unwrap();
-        let source_dir = TempDir::new().unwrap();
-        let source_file = source_dir.path().join("myconfig");
-        fs::write(&source_file, "content").unwrap();
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
 
-        let local_path = add_file(&source_file, repo.path()).unwrap();
+        let mut manifest = Manifest::empty();
+        let local_path = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
 
         // File exists in repo
-        assert!(local_path.exists());
-        assert_eq!(local_path, repo.path().join("myconfig"));
+        assert_eq!(local_path, Path::new("myconfig"));
+        assert!(f.repo.path().join("myconfig").exists());
+        assert!(manifest.contains(&local_path));
         // Original is now a symlink
         assert!(
             source_file
@@ -105,12 +446,615 @@ mod tests {
                 .file_type()
                 .is_symlink()
         );
+        assert_eq!(fs::read_to_string(&source_file).unwrap(), "content");
+    }
+
+    #[test]
+    fn mirrors_target_directories() {
+        let f = Fixture::new();
+        let nvim = f.source(".config/nvim/init.lua");
+        let wezterm = f.source(".config/wezterm/init.lua");
+
+        let mut manifest = Manifest::empty();
+        let ctx = f.ctx();
+        let first = AddCommand::add_to_manifest(&mut manifest, &nvim, &AddOptions::default(), &ctx)
+            .unwrap();
+        let second =
+            AddCommand::add_to_manifest(&mut manifest, &wezterm, &AddOptions::default(), &ctx)
+                .unwrap();
+
+        assert_eq!(first, Path::new("config/nvim/init.lua"));
+        assert_eq!(second, Path::new("config/wezterm/init.lua"));
+        assert!(f.repo.path().join("config/nvim/init.lua").is_file());
+        assert!(f.repo.path().join("config/wezterm/init.lua").is_file());
+    }
+
+    fn unfold(exclude: &[&str]) -> AddOptions {
+        AddOptions {
+            unfold: true,
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+            ..AddOptions::default()
+        }
+    }
+
+    #[test]
+    fn keeps_legacy_manifest_at_version_1() {
+        let f = Fixture::new();
+        let ctx = f.ctx();
+        fs::write(ctx.manifest_path(), "\"zshrc\" = \"~/.zshrc\"\n").unwrap();
+        let mut manifest = Manifest::load_from(&ctx.manifest_path()).unwrap();
+
+        let source_file = f.source(".vimrc");
+        AddCommand::add_to_manifest(&mut manifest, &source_file, &AddOptions::default(), &ctx)
+            .unwrap();
+
+        // Directories fold without saying so
+        let init = f.source(".config/nvim/init.lua");
+        let local_path = AddCommand::add_to_manifest(
+            &mut manifest,
+            init.parent().unwrap(),
+            &AddOptions::default(),
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(manifest.entry(&local_path).unwrap().dir, None);
+
+        let saved = Manifest::load_from(&ctx.manifest_path()).unwrap();
+        assert_eq!(saved.version(), 1);
+        assert!(saved.contains(Path::new("vimrc")));
+
+        // Newer features are refused and the file is put back
+        let encrypted = f.source(".netrc");
+        let options = AddOptions {
+            encrypt: true,
+            ..AddOptions::default()
+        };
+        ctx.key
+            .set(Rc::new(Key::Passphrase("pass".to_string().into())))
+            .unwrap();
+        let result = AddCommand::add_to_manifest(&mut manifest, &encrypted, &options, &ctx);
+        assert!(matches!(result, Err(Error::NeedsMigration(_))));
+        assert_eq!(fs::read_to_string(&encrypted).unwrap(), "content");
+        assert_eq!(
+            Manifest::load_from(&ctx.manifest_path()).unwrap().version(),
+            1
+        );
+    }
+
+    #[test]
+    fn links_relative_when_manifest_default_is_set() {
+        let f = Fixture::new();
+        let source_file = f.source(".config/app/config");
+
+        let mut manifest = Manifest::parse("version = 2\n[defaults]\nrelative = true\n").unwrap();
+        AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        assert!(fs::read_link(&source_file).unwrap().is_relative());
+        assert_eq!(fs::read_to_string(&source_file).unwrap(), "content");
+    }
+
+    #[test]
+    fn records_folded_directory() {
+        let f = Fixture::new();
+        f.source(".config/fish/config.fish");
+        let dir = f.home.path().join(".config/fish");
+
+        let mut manifest = Manifest::empty();
+        let local_path =
+            AddCommand::add_to_manifest(&mut manifest, &dir, &AddOptions::default(), &f.ctx())
+                .unwrap();
+
+        assert_eq!(
+            manifest.entry(&local_path).unwrap().dir,
+            Some(DirMode::Fold)
+        );
+        assert!(dir.symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(f.repo.path().join("config/fish/config.fish").is_file());
+    }
+
+    #[test]
+    fn unfolds_directory_into_per_file_links() {
+        let f = Fixture::new();
+        let config = f.source(".config/fish/config.fish");
+        let function = f.source(".config/fish/functions/ls.fish");
+        let variables = f.source(".config/fish/fish_variables");
+        let dir = f.home.path().join(".config/fish");
+
+        let mut manifest = Manifest::empty();
+        let local_path = AddCommand::add_to_manifest(
+            &mut manifest,
+            &dir,
+            &unfold(&["fish_variables"]),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        let entry = manifest.entry(&local_path).unwrap();
+        assert!(entry.is_unfolded());
+        assert_eq!(entry.exclude, ["fish_variables"]);
+        // The directory stays real, tracked files become links
+        assert!(dir.symlink_metadata().unwrap().is_dir());
+        assert!(config.symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(
+            function
+                .symlink_metadata()
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert!(variables.symlink_metadata().unwrap().is_file());
+        assert!(
+            f.repo
+                .path()
+                .join("config/fish/functions/ls.fish")
+                .is_file()
+        );
+        assert!(!f.repo.path().join("config/fish/fish_variables").exists());
+    }
+
+    #[test]
+    fn unfold_requires_directory() {
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
+
+        let result = AddCommand::plan_add(&Manifest::empty(), &source_file, &unfold(&[]), &f.ctx());
+        assert!(matches!(result, Err(Error::NotADirectory(_))));
+    }
+
+    #[test]
+    fn rejects_invalid_glob() {
+        let f = Fixture::new();
+        f.source(".config/fish/config.fish");
+        let dir = f.home.path().join(".config/fish");
+
+        let result = AddCommand::plan_add(&Manifest::empty(), &dir, &unfold(&["[x"]), &f.ctx());
+        assert!(matches!(result, Err(Error::InvalidGlob(..))));
+    }
+
+    #[test]
+    fn rolls_back_unfolded_directory() {
+        let f = Fixture::new();
+        let config = f.source(".config/fish/config.fish");
+        f.source(".config/fish/functions/ls.fish");
+        let dir = f.home.path().join(".config/fish");
+
+        // Fail on the last symlink, after both files have moved
+        let mut manifest = Manifest::empty();
+        let result = AddCommand::add_all_to_manifest_with(
+            &mut manifest,
+            &[dir],
+            &unfold(&[]),
+            &f.ctx(),
+            plan::fail_at(5),
+        );
+
+        assert!(result.is_err());
+        assert!(config.symlink_metadata().unwrap().is_file());
+        assert_eq!(fs::read_dir(f.repo.path()).unwrap().count(), 0);
+        assert!(manifest.is_empty());
+    }
+
+    #[test]
+    fn stores_file_at_explicit_repo_path() {
+        let f = Fixture::new();
+        let source_file = f.source(".bashrc");
+
+        let mut manifest = Manifest::empty();
+        let local_path = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions {
+                repo_path: Some("shell/bashrc".into()),
+                ..AddOptions::default()
+            },
+            &f.ctx(),
+        )
+        .unwrap();
+
+        assert_eq!(local_path, Path::new("shell/bashrc"));
+        assert!(f.repo.path().join("shell/bashrc").is_file());
+    }
+
+    #[test]
+    fn rejects_repo_path_outside_repository() {
+        let f = Fixture::new();
+        let source_file = f.source(".bashrc");
+
+        for repo_path in ["../bashrc", ".git/hooks/pre-commit", MANIFEST_FILE] {
+            let result = AddCommand::plan_add(
+                &Manifest::empty(),
+                &source_file,
+                &AddOptions {
+                    repo_path: Some(repo_path.into()),
+                    ..AddOptions::default()
+                },
+                &f.ctx(),
+            );
+            assert!(
+                matches!(result, Err(Error::InvalidRepoPath(_))),
+                "{repo_path}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_to_overwrite_untracked_repo_file() {
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
+        fs::write(f.repo.path().join("myconfig"), "untracked").unwrap();
+
+        let result = AddCommand::plan_add(
+            &Manifest::empty(),
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn dry_run_leaves_file_in_place() {
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
+
+        let mut ctx = f.ctx();
+        ctx.dry_run = true;
+        let mut manifest = Manifest::empty();
+        AddCommand::add_to_manifest(&mut manifest, &source_file, &AddOptions::default(), &ctx)
+            .unwrap();
+
+        assert!(!f.repo.path().join("myconfig").exists());
+        assert!(source_file.symlink_metadata().unwrap().is_file());
+    }
+
+    #[test]
+    fn plans_move_then_symlink() {
+        let f = Fixture::new();
+        let source_file = f.source(".config/app/config");
+
+        let (_, plan) = AddCommand::plan_add(
+            &Manifest::empty(),
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        let local = f.repo.path().join("config/app/config");
+        assert_eq!(
+            plan.actions(),
+            [
+                Action::CreateDir(f.repo.path().join("config/app")),
+                Action::Move {
+                    from: source_file.clone(),
+                    to: local.clone(),
+                },
+                Action::Symlink {
+                    original: local,
+                    link: source_file,
+                },
+            ]
+        );
     }
 
     #[test]
     fn returns_error_for_invalid_path() {
-        let repo = TempDir::new().unwrap();
-        let result = add_file(Path::new("/"), repo.path());
+        let f = Fixture::new();
+        let result = AddCommand::plan_add(
+            &Manifest::empty(),
+            Path::new("/"),
+            &AddOptions::default(),
+            &f.ctx(),
+        );
         assert!(matches!(result, Err(Error::NotFound(_))));
     }
+
+    #[test]
+    fn returns_error_for_missing_file() {
+        let f = Fixture::new();
+        let result = AddCommand::plan_add(
+            &Manifest::empty(),
+            Path::new("/nonexistent/file"),
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+
+    /// Assert that a failed add left the file untouched at its original location
+    fn assert_not_added(repo: &Path, source_file: &Path, manifest: &Manifest) {
+        let metadata = source_file.symlink_metadata().unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(fs::read_to_string(source_file).unwrap(), "content");
+        assert_eq!(
+            fs::read_dir(repo)
+                .unwrap()
+                .filter(|e| e.as_ref().unwrap().file_name() != MANIFEST_FILE)
+                .count(),
+            0
+        );
+        assert!(manifest.is_empty());
+    }
+
+    #[test]
+    fn rolls_back_when_any_filesystem_step_fails() {
+        for fail_at in 0..3 {
+            let f = Fixture::new();
+            let source_file = f.source(".config/app/config");
+
+            let mut manifest = Manifest::empty();
+            let result = AddCommand::add_all_to_manifest_with(
+                &mut manifest,
+                std::slice::from_ref(&source_file),
+                &AddOptions::default(),
+                &f.ctx(),
+                plan::fail_at(fail_at),
+            );
+
+            assert!(result.is_err(), "step {fail_at}");
+            assert_not_added(f.repo.path(), &source_file, &manifest);
+        }
+    }
+
+    #[test]
+    fn rolls_back_when_manifest_cannot_be_saved() {
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
+        // A directory in place of the manifest makes the final rename fail
+        fs::create_dir(f.repo.path().join(MANIFEST_FILE)).unwrap();
+        fs::write(f.repo.path().join(MANIFEST_FILE).join("keep"), "").unwrap();
+
+        let mut manifest = Manifest::empty();
+        let result = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+
+        assert!(result.is_err());
+        assert_not_added(f.repo.path(), &source_file, &manifest);
+    }
+
+    #[test]
+    fn saves_manifest_in_repo() {
+        let f = Fixture::new();
+        let source_file = f.source("myconfig");
+
+        let mut manifest = Manifest::empty();
+        AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        let saved = Manifest::load_from(&f.repo.path().join(MANIFEST_FILE)).unwrap();
+        assert!(saved.contains(Path::new("myconfig")));
+    }
+
+    #[test]
+    fn encrypt_stores_ciphertext_and_keeps_plaintext() {
+        let f = Fixture::new();
+        let source_file = f.source(".netrc");
+        let ctx = f.ctx();
+        ctx.key
+            .set(Rc::new(Key::Passphrase("pass".to_string().into())))
+            .unwrap();
+        let options = AddOptions {
+            encrypt: true,
+            ..AddOptions::default()
+        };
+
+        let mut manifest = Manifest::empty();
+        let local_path =
+            AddCommand::add_to_manifest(&mut manifest, &source_file, &options, &ctx).unwrap();
+
+        let stored = fs::read_to_string(f.repo.path().join(&local_path)).unwrap();
+        assert!(stored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert!(!source_file.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&source_file).unwrap(), "content");
+
+        let link = &ctx.links(&manifest).unwrap()[0];
+        let state = State::load_from(&ctx.state_path()).unwrap();
+        let vars = ctx.vars(&manifest);
+        assert_eq!(ctx.inspect(link, &state, &vars).unwrap(), LinkState::Linked);
+    }
+
+    #[test]
+    fn encrypted_files_deploy_privately() {
+        let f = Fixture::new();
+        let source_file = f.source(".netrc");
+        fs::set_permissions(&source_file, fs::Permissions::from_mode(0o644)).unwrap();
+        let ctx = f.ctx();
+        ctx.key
+            .set(Rc::new(Key::Passphrase("pass".to_string().into())))
+            .unwrap();
+        let options = AddOptions {
+            encrypt: true,
+            ..AddOptions::default()
+        };
+
+        let mut manifest = Manifest::empty();
+        let local_path =
+            AddCommand::add_to_manifest(&mut manifest, &source_file, &options, &ctx).unwrap();
+        assert_eq!(manifest.entry(&local_path).unwrap().permissions, None);
+        let mode = |path: &Path| path.metadata().unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&source_file), 0o600);
+
+        fs::remove_file(&source_file).unwrap();
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(fs::read_to_string(&source_file).unwrap(), "content");
+        assert_eq!(mode(&source_file), 0o600);
+    }
+
+    #[test]
+    fn captures_permissions() {
+        let f = Fixture::new();
+        let source_file = f.source(".ssh/config");
+        fs::set_permissions(&source_file, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let mut manifest = Manifest::empty();
+        let local_path = AddCommand::add_to_manifest(
+            &mut manifest,
+            &source_file,
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.entry(&local_path).unwrap().permissions,
+            Some(0o600)
+        );
+    }
+
+    #[test]
+    fn captures_permissions_shared_by_unfolded_files() {
+        let f = Fixture::new();
+        let gnupg = f.home.path().join(".gnupg");
+        for name in ["gpg.conf", "gpg-agent.conf"] {
+            let file = f.source(&format!(".gnupg/{name}"));
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        let ssh = f.home.path().join(".ssh");
+        fs::set_permissions(
+            f.source(".ssh/id_ed25519"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        fs::set_permissions(
+            f.source(".ssh/id_ed25519.pub"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        let mut manifest = Manifest::empty();
+        let ctx = f.ctx();
+        let private =
+            AddCommand::add_to_manifest(&mut manifest, &gnupg, &unfold(&[]), &ctx).unwrap();
+        let mixed = AddCommand::add_to_manifest(&mut manifest, &ssh, &unfold(&[]), &ctx).unwrap();
+
+        assert_eq!(manifest.entry(&private).unwrap().permissions, Some(0o600));
+        assert_eq!(manifest.entry(&mixed).unwrap().permissions, None);
+    }
+
+    #[test]
+    fn adds_paths_and_globs_as_one_batch() {
+        let f = Fixture::new();
+        let git_config = f.source(".config/git/config");
+        let git_ignore = f.source(".config/git/ignore");
+        let tmux = f.source(".tmux.conf");
+        let pattern = f.home.path().join(".config/git/*");
+
+        let mut manifest = Manifest::empty();
+        let added = AddCommand::add_all_to_manifest(
+            &mut manifest,
+            &[pattern, tmux.clone()],
+            &AddOptions::default(),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        let locals: Vec<_> = added.iter().map(|(local, _)| local.clone()).collect();
+        assert_eq!(
+            locals,
+            [
+                PathBuf::from("config/git/config"),
+                PathBuf::from("config/git/ignore"),
+                PathBuf::from("tmux.conf")
+            ]
+        );
+        for file in [&git_config, &git_ignore, &tmux] {
+            assert!(file.symlink_metadata().unwrap().is_symlink());
+        }
+        let saved = Manifest::load_from(&f.ctx().manifest_path()).unwrap();
+        assert!(locals.iter().all(|local| saved.contains(local)));
+    }
+
+    #[test]
+    fn validates_every_path_before_moving_any() {
+        let f = Fixture::new();
+        let vimrc = f.source(".vimrc");
+        let other = f.source("vimrc");
+        let add = |paths: &[PathBuf], options: &AddOptions| {
+            AddCommand::add_all_to_manifest(&mut Manifest::empty(), paths, options, &f.ctx())
+        };
+
+        assert!(matches!(
+            add(&[vimrc.clone(), vimrc.clone()], &AddOptions::default()),
+            Err(Error::DuplicatePath(_))
+        ));
+        // Both are stored as "vimrc"
+        assert!(matches!(
+            add(&[vimrc.clone(), other.clone()], &AddOptions::default()),
+            Err(Error::RepoPathCollision(..))
+        ));
+        assert!(matches!(
+            add(
+                &[vimrc.clone(), f.home.path().join("*.none")],
+                &AddOptions::default()
+            ),
+            Err(Error::NotFound(_))
+        ));
+        let options = AddOptions {
+            repo_path: Some("vim/vimrc".into()),
+            ..AddOptions::default()
+        };
+        assert!(matches!(
+            add(&[vimrc.clone(), other.clone()], &options),
+            Err(Error::RepoPathForMany)
+        ));
+
+        assert!(vimrc.symlink_metadata().unwrap().is_file());
+        assert!(other.symlink_metadata().unwrap().is_file());
+    }
+
+    #[test]
+    fn rejects_special_files() {
+        let f = Fixture::new();
+        let socket = f.home.path().join("agent.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let result = AddCommand::add_to_manifest(
+            &mut Manifest::empty(),
+            &socket,
+            &AddOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::SpecialFile(_))));
+    }
+
+    #[test]
+    fn rolls_back_the_whole_batch() {
+        for fail_at in 0..7 {
+            let f = Fixture::new();
+            let files = [
+                f.source(".config/git/config"),
+                f.source(".config/git/ignore"),
+                f.source(".tmux.conf"),
+            ];
+            let mut manifest = Manifest::empty();
+            let result = AddCommand::add_all_to_manifest_with(
+                &mut manifest,
+                &files,
+                &AddOptions::default(),
+                &f.ctx(),
+                plan::fail_at(fail_at),
+            );
+
+            assert!(result.is_err(), "step {fail_at}");
+            for file in &files {
+                assert_not_added(f.repo.path(), file, &manifest);
+            }
+        }
+    }
 }
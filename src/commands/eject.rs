@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+
+use crate::commands::{Command, RemoveCommand, save_manifest_and_state};
+use crate::context::Context;
+use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::plan::Plan;
+use crate::state::State;
+
+pub struct EjectCommand;
+
+impl EjectCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Work out the filesystem changes needed to stop tracking every entry
+    /// deployed on this machine.
+    ///
+    /// Entries of other profiles or whose condition fails stay tracked, and
+    /// so do entries whose target is missing, since there is nowhere to
+    /// restore them to.
+    pub fn plan_eject(
+        manifest: &Manifest,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<(EjectResult, Plan)> {
+        let mut result = EjectResult::default();
+        let mut plan = Plan::new();
+
+        for (file, entry, target) in ctx.entries(manifest)? {
+            if !ctx.condition_holds(entry)? {
+                continue;
+            }
+            if target.symlink_metadata().is_err() {
+                result.missing.push((file.to_path_buf(), target));
+                continue;
+            }
+            let (_, restore) = RemoveCommand::plan_remove(manifest, file, state, ctx)?;
+            plan.append(restore);
+            result.ejected.push((file.to_path_buf(), target));
+        }
+
+        Ok((result, plan))
+    }
+
+    /// Core logic separated for testing.
+    ///
+    /// Runs as a single transaction: if restoring any entry fails, every
+    /// entry is put back and the manifest is left unchanged.
+    pub fn eject_manifest(manifest: &mut Manifest, ctx: &Context) -> Result<EjectResult> {
+        let mut state = State::load_from(&ctx.state_path())?;
+        let (result, plan) = Self::plan_eject(manifest, &mut state, ctx)?;
+
+        let mut updated = manifest.clone();
+        for (file, _) in &result.ejected {
+            updated.remove(file);
+        }
+
+        let transaction = ctx.begin(&plan)?;
+        if !ctx.dry_run
+            && let Err(e) = save_manifest_and_state(&updated, manifest, &state, ctx)
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
+        *manifest = updated;
+        Ok(result)
+    }
+}
+
+impl Default for EjectCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct EjectResult {
+    /// Entries that were removed, with the target they were restored to
+    pub ejected: Vec<(PathBuf, PathBuf)>,
+    /// Entries left tracked because their target is missing
+    pub missing: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Command for EjectCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let mut manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let result = Self::eject_manifest(&mut manifest, ctx)?;
+
+        for (file, target) in &result.missing {
+            eprintln!(
+                "Warning: kept {} tracked ({} is missing)",
+                file.display(),
+                target.display()
+            );
+        }
+
+        if result.ejected.is_empty() {
+            println!("Nothing to eject");
+        }
+
+        if ctx.dry_run {
+            return Ok(());
+        }
+
+        for (file, target) in &result.ejected {
+            println!(
+                "Removed {} (restored to {})",
+                file.display(),
+                target.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{SyncCommand, SyncOptions};
+    use crate::entry::{Entry, LinkMode};
+    use crate::error::Error;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    /// Test helper that syncs a symlinked `vimrc` and a copied `gitconfig`
+    fn synced(repo: &TempDir, target_dir: &TempDir) -> Manifest {
+        fs::write(repo.path().join("vimrc"), "set nu").unwrap();
+        fs::write(repo.path().join("gitconfig"), "[user]").unwrap();
+        let mut manifest = Manifest::empty();
+        manifest
+            .insert("vimrc".into(), &target_dir.path().join(".vimrc"))
+            .unwrap();
+        manifest.insert_entry(
+            "gitconfig".into(),
+            Entry {
+                mode: LinkMode::Copy,
+                ..Entry::new(target_dir.path().join(".gitconfig"))
+            },
+        );
+        let ctx = Context::new(repo.path());
+        manifest.save_to(&ctx.manifest_path()).unwrap();
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn restores_every_entry_as_a_regular_file() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut manifest = synced(&repo, &target_dir);
+        let ctx = Context::new(repo.path());
+
+        let result = EjectCommand::eject_manifest(&mut manifest, &ctx).unwrap();
+
+        assert_eq!(result.ejected.len(), 2);
+        assert!(manifest.is_empty());
+        assert!(
+            Manifest::load_from(&ctx.manifest_path())
+                .unwrap()
+                .is_empty()
+        );
+        for (name, content) in [(".vimrc", "set nu"), (".gitconfig", "[user]")] {
+            let target = target_dir.path().join(name);
+            assert!(target.symlink_metadata().unwrap().is_file());
+            assert_eq!(fs::read_to_string(target).unwrap(), content);
+        }
+        assert!(!repo.path().join("vimrc").exists());
+        assert_eq!(
+            State::load_from(&ctx.state_path()).unwrap(),
+            State::default()
+        );
+    }
+
+    #[test]
+    fn keeps_entries_with_missing_targets_tracked() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut manifest = synced(&repo, &target_dir);
+        let ctx = Context::new(repo.path());
+        let vimrc = target_dir.path().join(".vimrc");
+        fs::remove_file(&vimrc).unwrap();
+
+        let result = EjectCommand::eject_manifest(&mut manifest, &ctx).unwrap();
+
+        assert_eq!(result.missing, [(PathBuf::from("vimrc"), vimrc)]);
+        assert_eq!(result.ejected.len(), 1);
+        assert!(manifest.contains(Path::new("vimrc")));
+        assert!(!manifest.contains(Path::new("gitconfig")));
+        assert_eq!(
+            fs::read_to_string(repo.path().join("vimrc")).unwrap(),
+            "set nu"
+        );
+        let gitconfig = target_dir.path().join(".gitconfig");
+        assert_eq!(fs::read_to_string(gitconfig).unwrap(), "[user]");
+    }
+
+    #[test]
+    fn failure_leaves_every_entry_tracked() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut manifest = synced(&repo, &target_dir);
+        let ctx = Context::new(repo.path());
+        fs::write(target_dir.path().join(".gitconfig"), "[user] edited").unwrap();
+
+        let result = EjectCommand::eject_manifest(&mut manifest, &ctx);
+
+        assert!(matches!(result, Err(Error::ModifiedCopy(_))));
+        assert_eq!(manifest.iter().count(), 2);
+        assert!(
+            target_dir
+                .path()
+                .join(".vimrc")
+                .symlink_metadata()
+                .unwrap()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut manifest = synced(&repo, &target_dir);
+        let mut ctx = Context::new(repo.path());
+        ctx.dry_run = true;
+
+        let result = EjectCommand::eject_manifest(&mut manifest, &ctx).unwrap();
+
+        assert_eq!(result.ejected.len(), 2);
+        assert!(repo.path().join("vimrc").exists());
+        assert_eq!(
+            Manifest::load_from(&ctx.manifest_path())
+                .unwrap()
+                .iter()
+                .count(),
+            2
+        );
+    }
+}
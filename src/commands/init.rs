@@ -1,9 +1,12 @@
 use std::fs;
+use std::io;
 use std::path::Path;
 
 use crate::commands::Command;
+use crate::context::Context;
 use crate::error::{Error, Result};
-use crate::manifest::MANIFEST_FILE;
+use crate::manifest::Manifest;
+use crate::state::STATE_FILE;
 
 pub struct InitCommand;
 
@@ -13,6 +16,31 @@ impl InitCommand {
     }
 }
 
+/// Add the machine-local state file to the repository's `.gitignore`,
+/// unless it is already listed there.
+fn ignore_state_file(repo: &Path) -> Result<()> {
+    let path = repo.join(".gitignore");
+    let mut content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let entry = format!("/{STATE_FILE}");
+    if content
+        .lines()
+        .any(|line| line.trim() == entry || line.trim() == STATE_FILE)
+    {
+        return Ok(());
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&entry);
+    content.push('\n');
+    fs::write(path, content)?;
+    Ok(())
+}
+
 impl Default for InitCommand {
     fn default() -> Self {
         Self::new()
@@ -20,13 +48,21 @@ impl Default for InitCommand {
 }
 
 impl Command for InitCommand {
-    fn execute(self) -> Result<()> {
-        let path = Path::new(MANIFEST_FILE);
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let path = ctx.manifest_path();
         if path.exists() {
-            return Err(Error::AlreadyExists(path.to_path_buf()));
+            return Err(Error::AlreadyExists(path));
+        }
+        if ctx.dry_run {
+            println!("Would create {}", path.display());
+            println!("Would ignore {STATE_FILE} in .gitignore");
+            return Ok(());
         }
-        fs::write(path, "")?;
-        println!("Initialized empty dot repository");
+        fs::create_dir_all(&ctx.repo)?;
+        Manifest::empty().save_to(&path)?;
+        // The state file describes this machine only
+        ignore_state_file(&ctx.repo)?;
+        println!("Initialized empty dot repository in {}", ctx.repo.display());
         Ok(())
     }
 }
@@ -34,25 +70,53 @@ impl Command for InitCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::manifest::{MANIFEST_FILE, MANIFEST_VERSION};
     use tempfile::TempDir;
 
-    /// Test helper that initializes a manifest at a specific path
-    fn init_at(path: &Path) -> Result<()> {
-        if path.exists() {
-            return Err(Error::AlreadyExists(path.to_path_buf()));
-        }
-        fs::write(path, "")?;
-        Ok(())
+    #[test]
+    fn creates_manifest_file() {
+        let temp = TempDir::new().unwrap();
+
+        InitCommand::new()
+            .execute(&Context::new(temp.path()))
+            .unwrap();
+
+        let manifest = Manifest::load_from(&temp.path().join(MANIFEST_FILE)).unwrap();
+        assert_eq!(manifest.version(), MANIFEST_VERSION);
+        assert!(manifest.is_empty());
     }
 
     #[test]
-    fn creates_manifest_file() {
+    fn ignores_state_file() {
         let temp = TempDir::new().unwrap();
-        let manifest_path = temp.path().join(MANIFEST_FILE);
+        fs::write(temp.path().join(".gitignore"), "*.swp").unwrap();
+
+        InitCommand::new()
+            .execute(&Context::new(temp.path()))
+            .unwrap();
+
+        let gitignore = fs::read_to_string(temp.path().join(".gitignore")).unwrap();
+        assert_eq!(gitignore, format!("*.swp\n/{STATE_FILE}\n"));
+
+        // Listed once, even if init runs again after the manifest is gone
+        fs::remove_file(temp.path().join(MANIFEST_FILE)).unwrap();
+        InitCommand::new()
+            .execute(&Context::new(temp.path()))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.path().join(".gitignore")).unwrap(),
+            gitignore
+        );
+    }
+
+    #[test]
+    fn creates_missing_repository_directory() {
+        let temp = TempDir::new().unwrap();
+        let repo = temp.path().join("dotfiles");
 
-        init_at(&manifest_path).unwrap();
+        InitCommand::new().execute(&Context::new(&repo)).unwrap();
 
-        assert!(manifest_path.exists());
+        assert!(repo.join(MANIFEST_FILE).exists());
     }
 
     #[test]
@@ -61,7 +125,7 @@ mod tests {
         let manifest_path = temp.path().join(MANIFEST_FILE);
         fs::write(&manifest_path, "").unwrap();
 
-        let result = init_at(&manifest_path);
+        let result = InitCommand::new().execute(&Context::new(temp.path()));
 
         assert!(matches!(result, Err(Error::AlreadyExists(_))));
     }
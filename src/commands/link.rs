@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+
+use crate::commands::{Command, SyncCommand, SyncOptions, SyncResult, save_manifest_and_state};
+use crate::conflict::ConflictStrategy;
+use crate::context::{Context, Link};
+use crate::entry::{DirMode, Entry, LinkMode};
+use crate::error::{Error, Result};
+use crate::link::LinkState;
+use crate::manifest::Manifest;
+use crate::path::{is_repo_relative, to_lexical_absolute};
+use crate::plan::Plan;
+use crate::state::State;
+
+pub struct LinkCommand {
+    file_path: PathBuf,
+    target: PathBuf,
+    options: LinkOptions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LinkOptions {
+    /// What to do when a file already exists at the target; without one,
+    /// the command refuses
+    pub on_conflict: Option<ConflictStrategy>,
+}
+
+impl LinkCommand {
+    pub fn new(file_path: PathBuf, target: PathBuf, options: LinkOptions) -> Self {
+        Self {
+            file_path,
+            target,
+            options,
+        }
+    }
+
+    /// The manifest key of `file_path`, given relative to the repository
+    /// root or as a path inside the repository.
+    fn repo_key(file_path: &Path, ctx: &Context) -> Result<PathBuf> {
+        if is_repo_relative(file_path) {
+            return Ok(file_path.to_path_buf());
+        }
+        let absolute = to_lexical_absolute(file_path)?;
+        match absolute.strip_prefix(&ctx.repo) {
+            Ok(key) if is_repo_relative(key) => Ok(key.to_path_buf()),
+            _ => Err(Error::InvalidRepoPath(file_path.to_path_buf())),
+        }
+    }
+
+    /// Work out the manifest entry for the repository file `file_path` and
+    /// the filesystem changes needed to deploy it at `target`.
+    pub fn plan_link(
+        manifest: &Manifest,
+        file_path: &Path,
+        target: &Path,
+        options: &LinkOptions,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<(PathBuf, Entry, SyncResult, Plan)> {
+        let local_path = Self::repo_key(file_path, ctx)?;
+        if manifest.contains(&local_path) {
+            return Err(Error::AlreadyTracked(local_path));
+        }
+        let local = ctx.local(&local_path);
+        if local.symlink_metadata().is_err() {
+            return Err(Error::NotFound(local));
+        }
+
+        let target = if target.starts_with("~") {
+            ctx.expand(target)?
+        } else {
+            to_lexical_absolute(target)?
+        };
+        for (_, other) in ctx.targets(manifest) {
+            if other? == target {
+                return Err(Error::AlreadyTracked(target));
+            }
+        }
+
+        let mut entry = Entry::new(ctx.collapse(&target)?);
+        // Folding is the default, so version 1 manifests go without
+        if local.is_dir() && manifest.version() > 1 {
+            entry.dir = Some(DirMode::Fold);
+        }
+        let link = Link {
+            file: local_path.clone(),
+            target: target.clone(),
+            mode: LinkMode::Symlink,
+            relative: manifest.is_relative(&entry),
+            skipped: false,
+            permissions: None,
+        };
+
+        let vars = ctx.vars(manifest);
+        let deployable = matches!(
+            ctx.inspect(&link, state, &vars)?,
+            LinkState::Missing | LinkState::Linked
+        );
+        if !deployable && options.on_conflict.is_none() {
+            return Err(Error::AlreadyExists(target));
+        }
+
+        let on_conflict = options.on_conflict.unwrap_or_default();
+        let sync_options = SyncOptions {
+            // Overwriting also replaces a symlink to somewhere else
+            relink: on_conflict == ConflictStrategy::Overwrite,
+            on_conflict,
+        };
+        let mut result = SyncResult::default();
+        let mut plan = Plan::new();
+        SyncCommand::plan_link(
+            link,
+            &sync_options,
+            &vars,
+            state,
+            &mut plan,
+            &mut result,
+            ctx,
+        )?;
+
+        Ok((local_path, entry, result, plan))
+    }
+
+    /// Core logic separated for testing.
+    ///
+    /// Runs as a transaction: if linking or saving the manifest fails, the
+    /// target is put back and the manifest is left unchanged.
+    pub fn link_in_manifest(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        target: &Path,
+        options: &LinkOptions,
+        ctx: &Context,
+    ) -> Result<(PathBuf, SyncResult)> {
+        let mut state = State::load_from(&ctx.state_path())?;
+        let (local_path, entry, result, plan) =
+            Self::plan_link(manifest, file_path, target, options, &mut state, ctx)?;
+
+        let mut updated = manifest.clone();
+        updated.insert_entry(local_path.clone(), entry);
+
+        let transaction = ctx.begin(&plan)?;
+        if !ctx.dry_run
+            && let Err(e) = save_manifest_and_state(&updated, manifest, &state, ctx)
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
+        *manifest = updated;
+        Ok((local_path, result))
+    }
+}
+
+impl Command for LinkCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let mut manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let (local_path, result) = Self::link_in_manifest(
+            &mut manifest,
+            &self.file_path,
+            &self.target,
+            &self.options,
+            ctx,
+        )?;
+
+        result.print(ctx.dry_run);
+        if !ctx.dry_run {
+            println!("Tracking {}", local_path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+    use std::fs;
+
+    impl Fixture {
+        /// A repository holding an untracked `vim/vimrc`
+        fn with_vimrc() -> Self {
+            let f = Self::new();
+            fs::create_dir(f.repo.path().join("vim")).unwrap();
+            fs::write(f.repo.path().join("vim/vimrc"), "set nu").unwrap();
+            f
+        }
+
+        fn link(&self, manifest: &mut Manifest, options: &LinkOptions) -> Result<PathBuf> {
+            LinkCommand::link_in_manifest(
+                manifest,
+                Path::new("vim/vimrc"),
+                Path::new("~/.vimrc"),
+                options,
+                &self.ctx(),
+            )
+            .map(|(local_path, _)| local_path)
+        }
+    }
+
+    #[test]
+    fn records_entry_and_creates_symlink() {
+        let f = Fixture::with_vimrc();
+        let mut manifest = Manifest::empty();
+
+        let local_path = f.link(&mut manifest, &LinkOptions::default()).unwrap();
+
+        assert_eq!(local_path, Path::new("vim/vimrc"));
+        assert_eq!(
+            manifest.entry(&local_path).unwrap().target,
+            Path::new("~/.vimrc")
+        );
+        assert!(
+            Manifest::load_from(&f.ctx().manifest_path())
+                .unwrap()
+                .contains(&local_path)
+        );
+        assert_eq!(
+            fs::read_to_string(f.home.path().join(".vimrc")).unwrap(),
+            "set nu"
+        );
+        assert!(
+            f.home
+                .path()
+                .join(".vimrc")
+                .symlink_metadata()
+                .unwrap()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn accepts_absolute_repository_paths() {
+        let f = Fixture::with_vimrc();
+        let key = LinkCommand::repo_key(&f.repo.path().join("vim/vimrc"), &f.ctx()).unwrap();
+        assert_eq!(key, Path::new("vim/vimrc"));
+        assert!(matches!(
+            LinkCommand::repo_key(f.home.path(), &f.ctx()),
+            Err(Error::InvalidRepoPath(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_existing_target_without_strategy() {
+        let f = Fixture::with_vimrc();
+        fs::write(f.home.path().join(".vimrc"), "mine").unwrap();
+        let mut manifest = Manifest::empty();
+
+        let result = f.link(&mut manifest, &LinkOptions::default());
+
+        assert!(matches!(result, Err(Error::AlreadyExists(_))));
+        assert!(manifest.is_empty());
+        assert_eq!(
+            fs::read_to_string(f.home.path().join(".vimrc")).unwrap(),
+            "mine"
+        );
+    }
+
+    #[test]
+    fn resolves_existing_target_with_strategy() {
+        let f = Fixture::with_vimrc();
+        fs::write(f.home.path().join(".vimrc"), "mine").unwrap();
+        let mut manifest = Manifest::empty();
+        let options = LinkOptions {
+            on_conflict: Some(ConflictStrategy::Adopt),
+        };
+
+        f.link(&mut manifest, &options).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(f.repo.path().join("vim/vimrc")).unwrap(),
+            "mine"
+        );
+        assert!(
+            f.home
+                .path()
+                .join(".vimrc")
+                .symlink_metadata()
+                .unwrap()
+                .is_symlink()
+        );
+    }
+
+    #[test]
+    fn refuses_missing_or_tracked_files() {
+        let f = Fixture::with_vimrc();
+        let mut manifest = Manifest::empty();
+        f.link(&mut manifest, &LinkOptions::default()).unwrap();
+
+        let result = f.link(&mut manifest, &LinkOptions::default());
+        assert!(matches!(result, Err(Error::AlreadyTracked(_))));
+
+        let result = LinkCommand::link_in_manifest(
+            &mut manifest,
+            Path::new("zshrc"),
+            Path::new("~/.zshrc"),
+            &LinkOptions::default(),
+            &f.ctx(),
+        );
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+}
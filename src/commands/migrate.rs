@@ -0,0 +1,121 @@
+use crate::commands::Command;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::manifest::{MANIFEST_VERSION, Manifest};
+
+pub struct MigrateCommand;
+
+impl MigrateCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Rewrite the manifest in the current schema.
+    ///
+    /// Returns the version it was migrated from, or `None` if it was
+    /// already current.
+    pub fn migrate(ctx: &Context) -> Result<Option<u32>> {
+        let path = ctx.manifest_path();
+        if !path.exists() {
+            return Err(Error::NotFound(path));
+        }
+
+        let mut manifest = Manifest::load_from(&path)?;
+        let version = manifest.version();
+        if version == MANIFEST_VERSION {
+            return Ok(None);
+        }
+
+        if ctx.dry_run {
+            println!(
+                "Would rewrite {} from version {version} to version {MANIFEST_VERSION}",
+                path.display()
+            );
+        } else {
+            manifest.upgrade();
+            manifest.save_to(&path)?;
+        }
+        Ok(Some(version))
+    }
+}
+
+impl Default for MigrateCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Command for MigrateCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let path = ctx.manifest_path();
+        match Self::migrate(ctx)? {
+            None => println!(
+                "{} is already at version {MANIFEST_VERSION}",
+                path.display()
+            ),
+            Some(_) if ctx.dry_run => {}
+            Some(from) => println!(
+                "Migrated {} from version {from} to version {MANIFEST_VERSION}",
+                path.display()
+            ),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const LEGACY: &str = r#""vimrc" = "~/.vimrc"
+"nvim/init.lua" = "~/.config/nvim/init.lua"
+"#;
+
+    #[test]
+    fn rewrites_legacy_manifest() {
+        let repo = TempDir::new().unwrap();
+        let ctx = Context::new(repo.path());
+        fs::write(ctx.manifest_path(), LEGACY).unwrap();
+
+        assert_eq!(MigrateCommand::migrate(&ctx).unwrap(), Some(1));
+
+        let content = fs::read_to_string(ctx.manifest_path()).unwrap();
+        assert!(content.starts_with("version = 2\n"));
+        let manifest = Manifest::parse(&content).unwrap();
+        assert_eq!(manifest.version(), MANIFEST_VERSION);
+        assert!(manifest.contains(Path::new("vimrc")));
+        assert!(manifest.contains(Path::new("nvim/init.lua")));
+    }
+
+    #[test]
+    fn leaves_current_manifest_alone() {
+        let repo = TempDir::new().unwrap();
+        let ctx = Context::new(repo.path());
+        let current = "version = 2\n\n[files]\nvimrc = \"~/.vimrc\" # keep\n";
+        fs::write(ctx.manifest_path(), current).unwrap();
+
+        assert_eq!(MigrateCommand::migrate(&ctx).unwrap(), None);
+        assert_eq!(fs::read_to_string(ctx.manifest_path()).unwrap(), current);
+    }
+
+    #[test]
+    fn dry_run_leaves_file_untouched() {
+        let repo = TempDir::new().unwrap();
+        let mut ctx = Context::new(repo.path());
+        ctx.dry_run = true;
+        fs::write(ctx.manifest_path(), LEGACY).unwrap();
+
+        assert_eq!(MigrateCommand::migrate(&ctx).unwrap(), Some(1));
+        assert_eq!(fs::read_to_string(ctx.manifest_path()).unwrap(), LEGACY);
+    }
+
+    #[test]
+    fn fails_without_manifest() {
+        let repo = TempDir::new().unwrap();
+        let result = MigrateCommand::migrate(&Context::new(repo.path()));
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+}
@@ -1,18 +1,52 @@
 mod add;
+mod eject;
 mod init;
+mod link;
+mod migrate;
+mod mv;
 mod remove;
+mod render;
+mod secret;
+mod status;
 mod sync;
+mod unlink;
 
-pub use add::AddCommand;
+pub use add::{AddCommand, AddOptions};
+pub use eject::EjectCommand;
 pub use init::InitCommand;
+pub use link::{LinkCommand, LinkOptions};
+pub use migrate::MigrateCommand;
+pub use mv::{MoveCommand, MoveOptions};
 pub use remove::RemoveCommand;
-pub use sync::SyncCommand;
+pub use render::RenderCommand;
+pub use secret::{SecretEditCommand, SecretKeygenCommand};
+pub use status::StatusCommand;
+pub use sync::{SyncCommand, SyncOptions, SyncResult};
+pub use unlink::{UnlinkCommand, UnlinkOptions};
 
+use crate::context::Context;
 use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::state::State;
 
 /// Trait for executable commands.
 /// Each command is self-contained and directly uses the abstractions it needs.
 pub trait Command {
     /// Execute the command
-    fn execute(self) -> Result<()>;
+    fn execute(self, ctx: &Context) -> Result<()>;
+}
+
+/// Save `updated` and then `state`, putting `original` back if the state
+/// cannot be saved, so a failed command changes neither.
+fn save_manifest_and_state(
+    updated: &Manifest,
+    original: &Manifest,
+    state: &State,
+    ctx: &Context,
+) -> Result<()> {
+    updated.save_to(&ctx.manifest_path())?;
+    state.save_to(&ctx.state_path()).inspect_err(|_| {
+        // Best effort: the state error is the one worth reporting
+        let _ = original.save_to(&ctx.manifest_path());
+    })
 }
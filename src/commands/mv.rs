@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+
+use crate::commands::{Command, save_manifest_and_state};
+use crate::context::Context;
+use crate::entry::LinkMode;
+use crate::error::{Error, Result};
+use crate::link::{LinkState, symlink_source};
+use crate::manifest::Manifest;
+use crate::path::{is_repo_relative, to_lexical_absolute};
+use crate::plan::{Action, Plan};
+use crate::state::State;
+
+pub struct MoveCommand {
+    file_path: PathBuf,
+    options: MoveOptions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MoveOptions {
+    /// Store the entry at this path inside the repository instead
+    pub repo_path: Option<PathBuf>,
+    /// Deploy the entry here instead
+    pub target: Option<PathBuf>,
+}
+
+/// Where an entry ends up after `dot mv`.
+#[derive(Debug, PartialEq)]
+pub struct Moved {
+    pub local: PathBuf,
+    pub target: PathBuf,
+}
+
+impl MoveCommand {
+    pub fn new(file_path: PathBuf, options: MoveOptions) -> Self {
+        Self { file_path, options }
+    }
+
+    /// Work out the filesystem changes needed to rename the entry `file_path`
+    /// in the repository and/or deploy it somewhere else.
+    ///
+    /// Deployed links follow the entry: symlinks are recreated to point at
+    /// the new repository path, and copies, hard links and rendered or
+    /// decrypted files are moved to the new target. Targets that are not
+    /// what `dot` deployed are left where they are.
+    pub fn plan_move(
+        manifest: &Manifest,
+        file_path: &Path,
+        options: &MoveOptions,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<(Moved, Plan)> {
+        let old_target = ctx
+            .target(manifest, file_path)
+            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
+        let old_local = ctx.local(file_path);
+        if old_local.symlink_metadata().is_err() {
+            return Err(Error::NotFound(old_local));
+        }
+
+        let local_path = match &options.repo_path {
+            Some(path) if !is_repo_relative(path) => {
+                return Err(Error::InvalidRepoPath(path.to_path_buf()));
+            }
+            Some(path) => path.to_path_buf(),
+            None => file_path.to_path_buf(),
+        };
+        let local = ctx.local(&local_path);
+        if local_path != file_path {
+            if manifest.contains(&local_path) {
+                return Err(Error::AlreadyTracked(local_path));
+            }
+            if local.symlink_metadata().is_ok() {
+                return Err(Error::AlreadyExists(local));
+            }
+        }
+
+        let target = match &options.target {
+            Some(path) if path.starts_with("~") => ctx.expand(path)?,
+            Some(path) => to_lexical_absolute(path)?,
+            None => old_target.clone(),
+        };
+        if target != old_target {
+            for (_, other) in ctx.targets(manifest) {
+                if other? == target {
+                    return Err(Error::AlreadyTracked(target));
+                }
+            }
+            if target.symlink_metadata().is_ok() {
+                return Err(Error::AlreadyExists(target));
+            }
+        }
+
+        let mut plan = Plan::new();
+        let moved = Moved {
+            local: local_path,
+            target,
+        };
+        if moved.local == file_path && moved.target == old_target {
+            return Ok((moved, plan));
+        }
+
+        if moved.local != file_path {
+            if let Some(parent) = local.parent() {
+                plan.ensure_dir(parent);
+            }
+            plan.push(Action::Move {
+                from: old_local,
+                to: local.clone(),
+            });
+        }
+
+        let vars = ctx.vars(manifest);
+        for link in ctx.links(manifest)? {
+            // Unfolded directories have one link per file below the entry
+            let Ok(relative) = link.file.strip_prefix(file_path) else {
+                continue;
+            };
+            if link.target != joined(&old_target, relative) {
+                continue;
+            }
+            let deployed = matches!(
+                ctx.inspect(&link, state, &vars)?,
+                LinkState::Linked | LinkState::WrongPermissions(_)
+            );
+            if !deployed {
+                continue;
+            }
+
+            let new_target = joined(&moved.target, relative);
+            match ctx.deploy_mode(&link)? {
+                LinkMode::Symlink => {
+                    plan.push(Action::Remove(link.target.clone()));
+                    if let Some(parent) = new_target.parent() {
+                        plan.ensure_dir(parent);
+                    }
+                    plan.push(Action::Symlink {
+                        original: symlink_source(
+                            &joined(&local, relative),
+                            &new_target,
+                            link.relative,
+                        )?,
+                        link: new_target,
+                    });
+                }
+                // The deployed file does not refer to the repository path,
+                // so it only has to move when the target does
+                _ if new_target != link.target => {
+                    if let Some(parent) = new_target.parent() {
+                        plan.ensure_dir(parent);
+                    }
+                    plan.push(Action::Move {
+                        from: link.target.clone(),
+                        to: new_target,
+                    });
+                }
+                _ => {}
+            }
+        }
+        state.relocate(&old_target, &moved.target);
+
+        Ok((moved, plan))
+    }
+
+    /// Core logic separated for testing.
+    ///
+    /// Runs as a transaction: if any step fails, the repository file and
+    /// deployed links are put back and the manifest is left unchanged.
+    pub fn move_in_manifest(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        options: &MoveOptions,
+        ctx: &Context,
+    ) -> Result<Moved> {
+        Self::move_in_manifest_with(manifest, file_path, options, ctx, |_| Ok(()))
+    }
+
+    /// Like `move_in_manifest`, running `check` before each filesystem step
+    /// (see `Plan::begin_with`).
+    fn move_in_manifest_with(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        options: &MoveOptions,
+        ctx: &Context,
+        check: impl FnMut(usize) -> Result<()>,
+    ) -> Result<Moved> {
+        let mut state = State::load_from(&ctx.state_path())?;
+        let (moved, plan) = Self::plan_move(manifest, file_path, options, &mut state, ctx)?;
+
+        let mut updated = manifest.clone();
+        let mut entry = manifest
+            .entry(file_path)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
+        if options.target.is_some() {
+            entry.target = ctx.collapse(&moved.target)?;
+        }
+        updated.rename(file_path, moved.local.clone());
+        updated.insert_entry(moved.local.clone(), entry);
+
+        let transaction = ctx.begin_with(&plan, check)?;
+        if !ctx.dry_run
+            && let Err(e) = save_manifest_and_state(&updated, manifest, &state, ctx)
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
+        *manifest = updated;
+        Ok(moved)
+    }
+}
+
+/// `base` joined with `relative`, without the trailing slash that joining an
+/// empty path adds.
+fn joined(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+impl Command for MoveCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let mut manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let file_path = ctx.resolve(&manifest, &self.file_path)?;
+        let moved = Self::move_in_manifest(&mut manifest, &file_path, &self.options, ctx)?;
+
+        if ctx.dry_run {
+            return Ok(());
+        }
+
+        println!(
+            "Moved {} to {} (deployed at {})",
+            file_path.display(),
+            moved.local.display(),
+            moved.target.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{SyncCommand, SyncOptions};
+    use crate::entry::Entry;
+    use crate::plan;
+    use crate::testing::Fixture;
+    use std::fs;
+
+    impl Fixture {
+        /// A repository tracking `vimrc` at `~/.vimrc` and `gitconfig` as a
+        /// copy at `~/.gitconfig`, both deployed
+        fn synced() -> (Self, Manifest) {
+            let f = Self::new();
+            fs::write(f.repo.path().join("vimrc"), "set nu").unwrap();
+            fs::write(f.repo.path().join("gitconfig"), "[user]").unwrap();
+            let mut manifest = Manifest::empty();
+            manifest.insert_entry("vimrc".into(), Entry::new("~/.vimrc".into()));
+            manifest.insert_entry(
+                "gitconfig".into(),
+                Entry {
+                    mode: LinkMode::Copy,
+                    ..Entry::new("~/.gitconfig".into())
+                },
+            );
+            manifest.save_to(&f.ctx().manifest_path()).unwrap();
+            SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &f.ctx()).unwrap();
+            (f, manifest)
+        }
+
+        fn status(&self, manifest: &Manifest) -> Vec<LinkState> {
+            let ctx = self.ctx();
+            let state = State::load_from(&ctx.state_path()).unwrap();
+            let vars = ctx.vars(manifest);
+            ctx.links(manifest)
+                .unwrap()
+                .iter()
+                .map(|link| ctx.inspect(link, &state, &vars).unwrap())
+                .collect()
+        }
+    }
+
+    fn rename(path: &str) -> MoveOptions {
+        MoveOptions {
+            repo_path: Some(path.into()),
+            ..MoveOptions::default()
+        }
+    }
+
+    fn retarget(path: &Path) -> MoveOptions {
+        MoveOptions {
+            target: Some(path.to_path_buf()),
+            ..MoveOptions::default()
+        }
+    }
+
+    #[test]
+    fn renames_repository_file_and_repoints_symlink() {
+        let (f, mut manifest) = Fixture::synced();
+
+        MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &rename("vim/vimrc"),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        assert!(!f.repo.path().join("vimrc").exists());
+        assert_eq!(
+            fs::read_link(f.home.path().join(".vimrc")).unwrap(),
+            f.repo.path().canonicalize().unwrap().join("vim/vimrc")
+        );
+        let saved = Manifest::load_from(&f.ctx().manifest_path()).unwrap();
+        assert!(saved.contains(Path::new("vim/vimrc")));
+        assert!(!saved.contains(Path::new("vimrc")));
+        assert_eq!(
+            manifest.entry(Path::new("vim/vimrc")).unwrap().target,
+            Path::new("~/.vimrc")
+        );
+        assert!(f.status(&manifest).iter().all(|s| *s == LinkState::Linked));
+    }
+
+    #[test]
+    fn retargets_symlinks_and_copies() {
+        let (f, mut manifest) = Fixture::synced();
+        let vimrc = f.home.path().join(".config/vim/vimrc");
+        let gitconfig = f.home.path().join(".config/git/config");
+
+        MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &retarget(&vimrc),
+            &f.ctx(),
+        )
+        .unwrap();
+        MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("gitconfig"),
+            &retarget(&gitconfig),
+            &f.ctx(),
+        )
+        .unwrap();
+
+        assert!(f.home.path().join(".vimrc").symlink_metadata().is_err());
+        assert!(f.home.path().join(".gitconfig").symlink_metadata().is_err());
+        assert!(vimrc.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&gitconfig).unwrap(), "[user]");
+        assert_eq!(
+            manifest.entry(Path::new("vimrc")).unwrap().target,
+            Path::new("~/.config/vim/vimrc")
+        );
+        // The copy's recorded hash moved with it
+        assert!(f.status(&manifest).iter().all(|s| *s == LinkState::Linked));
+    }
+
+    #[test]
+    fn refuses_to_overwrite_existing_paths() {
+        let (f, mut manifest) = Fixture::synced();
+        fs::write(f.repo.path().join("notes"), "").unwrap();
+        let ctx = f.ctx();
+
+        let result = MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &rename("gitconfig"),
+            &ctx,
+        );
+        assert!(matches!(result, Err(Error::AlreadyTracked(_))));
+        let result = MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &rename("notes"),
+            &ctx,
+        );
+        assert!(matches!(result, Err(Error::AlreadyExists(_))));
+        let result = MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &retarget(&f.home.path().join(".gitconfig")),
+            &ctx,
+        );
+        assert!(matches!(result, Err(Error::AlreadyTracked(_))));
+        let result = MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &rename("../vimrc"),
+            &ctx,
+        );
+        assert!(matches!(result, Err(Error::InvalidRepoPath(_))));
+        let result = MoveCommand::move_in_manifest(
+            &mut manifest,
+            Path::new("vimrc"),
+            &rename(".git/config"),
+            &ctx,
+        );
+        assert!(matches!(result, Err(Error::InvalidRepoPath(_))));
+
+        assert!(f.repo.path().join("vimrc").exists());
+    }
+
+    #[test]
+    fn rolls_back_when_any_step_fails() {
+        for fail_at in 0..5 {
+            let (f, mut manifest) = Fixture::synced();
+            let options = MoveOptions {
+                repo_path: Some("vim/vimrc".into()),
+                target: Some(f.home.path().join(".config/vim/vimrc")),
+            };
+
+            let result = MoveCommand::move_in_manifest_with(
+                &mut manifest,
+                Path::new("vimrc"),
+                &options,
+                &f.ctx(),
+                plan::fail_at(fail_at),
+            );
+
+            assert!(result.is_err(), "fail_at {fail_at}");
+            assert!(manifest.contains(Path::new("vimrc")));
+            assert!(f.repo.path().join("vimrc").exists());
+            assert!(f.status(&manifest).iter().all(|s| *s == LinkState::Linked));
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::commands::Command;
+use crate::commands::{Command, save_manifest_and_state};
+use crate::context::Context;
+use crate::entry::{LinkMode, files_under};
 use crate::error::{Error, Result};
+use crate::link::{same_file, same_inode};
 use crate::manifest::Manifest;
+use crate::plan::{Action, Plan};
+use crate::state::{State, content_hash};
 
 pub struct RemoveCommand {
     file_path: PathBuf,
@@ -14,40 +19,151 @@ impl RemoveCommand {
         Self { file_path }
     }
 
-    /// Core logic separated for testing
-    pub fn remove_from_manifest(manifest: &mut Manifest, file_path: &Path) -> Result<PathBuf> {
-        let symlink_path = manifest
-            .get(file_path)
+    /// Work out the filesystem changes needed to stop tracking `file_path`,
+    /// and forget what `state` recorded about its target.
+    pub fn plan_remove(
+        manifest: &Manifest,
+        file_path: &Path,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<(PathBuf, Plan)> {
+        let symlink_path = ctx
+            .target(manifest, file_path)
             .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
 
-        if !file_path.exists() {
+        let local_path = ctx.local(file_path);
+        if !local_path.exists() {
             return Err(Error::NotFound(file_path.to_path_buf()));
         }
 
+        state.forget(&symlink_path);
+        let mut plan = Plan::new();
+        let entry = manifest
+            .entry(file_path)
+            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
+        if !entry.is_unfolded() {
+            // Remove the deployed link or copy and restore file
+            Self::plan_restore(&mut plan, local_path, &symlink_path, entry.mode)?;
+            return Ok((symlink_path, plan));
+        }
+
+        // Restore each linked file into the real directory at the target
+        let selected = entry.selected_files(&local_path)?;
+        for relative in &selected {
+            Self::plan_restore(
+                &mut plan,
+                local_path.join(relative),
+                &symlink_path.join(relative),
+                entry.mode,
+            )?;
+        }
+
+        // Drop the emptied repository directory, unless it holds files the
+        // entry does not link
+        if selected.len() == files_under(&local_path)?.len() {
+            plan.push(Action::Remove(local_path));
+        }
+
+        Ok((symlink_path, plan))
+    }
+
+    /// Replace the deployed symlink or copy at `symlink_path` with the
+    /// repository file. A rendered template keeps its output instead.
+    fn plan_restore(
+        plan: &mut Plan,
+        local: PathBuf,
+        symlink_path: &Path,
+        mode: LinkMode,
+    ) -> Result<()> {
         let metadata = symlink_path.symlink_metadata()?;
-        if !metadata.file_type().is_symlink() {
-            return Err(Error::NotASymlink(symlink_path.clone()));
+        match mode {
+            // The rendered or decrypted output stays where it is; only the
+            // template or ciphertext goes
+            LinkMode::Template | LinkMode::Encrypted => {
+                plan.push(Action::Remove(local));
+                return Ok(());
+            }
+            LinkMode::Symlink if !metadata.file_type().is_symlink() => {
+                return Err(Error::NotASymlink(symlink_path.to_path_buf()));
+            }
+            // Never swap out a symlink someone pointed elsewhere
+            LinkMode::Symlink if !same_file(symlink_path, &local) => {
+                return Err(Error::ForeignSymlink(
+                    symlink_path.to_path_buf(),
+                    fs::read_link(symlink_path)?,
+                ));
+            }
+            // Refuse to throw away edits made to a copy or broken hard link
+            LinkMode::Copy | LinkMode::Hardlink
+                if !same_inode(symlink_path, &local)?
+                    && content_hash(symlink_path)? != content_hash(&local)? =>
+            {
+                return Err(Error::ModifiedCopy(symlink_path.to_path_buf()));
+            }
+            _ => {}
         }
 
-        // Remove symlink and restore file
-        fs::remove_file(&symlink_path)?;
-        fs::rename(file_path, &symlink_path)?;
+        plan.push(Action::Remove(symlink_path.to_path_buf()));
+        plan.push(Action::Move {
+            from: local,
+            to: symlink_path.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Core logic separated for testing.
+    ///
+    /// Runs as a transaction: if any step fails, the symlink and the
+    /// repository file are put back and the manifest and state are left
+    /// unchanged.
+    pub fn remove_from_manifest(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        ctx: &Context,
+    ) -> Result<PathBuf> {
+        Self::remove_from_manifest_with(manifest, file_path, ctx, |_| Ok(()))
+    }
+
+    /// Like `remove_from_manifest`, running `check` before each filesystem
+    /// step (see `Plan::begin_with`).
+    fn remove_from_manifest_with(
+        manifest: &mut Manifest,
+        file_path: &Path,
+        ctx: &Context,
+        check: impl FnMut(usize) -> Result<()>,
+    ) -> Result<PathBuf> {
+        let mut state = State::load_from(&ctx.state_path())?;
+        let (symlink_path, plan) = Self::plan_remove(manifest, file_path, &mut state, ctx)?;
+
+        let mut updated = manifest.clone();
+        updated.remove(file_path);
 
-        manifest.remove(file_path);
+        let transaction = ctx.begin_with(&plan, check)?;
+        if !ctx.dry_run
+            && let Err(e) = save_manifest_and_state(&updated, manifest, &state, ctx)
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
 
+        *manifest = updated;
         Ok(symlink_path)
     }
 }
 
 impl Command for RemoveCommand {
-    fn execute(self) -> Result<()> {
-        let mut manifest = Manifest::load()?;
-        let restored_path = Self::remove_from_manifest(&mut manifest, &self.file_path)?;
-        manifest.save()?;
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let mut manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let file_path = ctx.resolve(&manifest, &self.file_path)?;
+        let restored_path = Self::remove_from_manifest(&mut manifest, &file_path, ctx)?;
+
+        if ctx.dry_run {
+            return Ok(());
+        }
 
         println!(
             "Removed {} (restored to {})",
-            self.file_path.display(),
+            file_path.display(),
             restored_path.display()
         );
         Ok(())
@@ -57,30 +173,28 @@ impl Command for RemoveCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::manifest::MANIFEST_FILE;
+    use crate::plan;
     use std::os::unix::fs::symlink;
     use tempfile::TempDir;
 
-    /// Test helper that removes a tracked file using absolute paths
-    fn remove_tracked_file(local_file: &Path, symlink_target: &Path) -> Result<()> {
-        let metadata = symlink_target.symlink_metadata()?;
-        if !metadata.file_type().is_symlink() {
-            return Err(Error::NotASymlink(symlink_target.to_path_buf()));
-        }
-
-        if !local_file.exists() {
-            return Err(Error::NotFound(local_file.to_path_buf()));
-        }
-
-        fs::remove_file(symlink_target)?;
-        fs::rename(local_file, symlink_target)?;
-
-        Ok(())
+    /// Test helper that tracks `repo/myfile` at `original_path`
+    fn tracked_manifest(repo: &Path, original_path: &Path) -> Manifest {
+        fs::write(repo.join("myfile"), "content").unwrap();
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), original_path).unwrap();
+        manifest
     }
 
     #[test]
     fn returns_error_if_not_tracked() {
+        let repo = TempDir::new().unwrap();
         let mut manifest = Manifest::empty();
-        let result = RemoveCommand::remove_from_manifest(&mut manifest, Path::new("nottracked"));
+        let result = RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("nottracked"),
+            &Context::new(repo.path()),
+        );
         assert!(matches!(result, Err(Error::NotFound(_))));
     }
 
@@ -89,16 +203,18 @@ mod tests {
         let repo = TempDir::new().unwrap();
         let original_dir = TempDir::new().unwrap();
         let original_path = original_dir.path().join("myfile");
-
-        // Create local file
-        let local_file = repo.path().join("myfile");
-        fs::write(&local_file, "content").unwrap();
+        let mut manifest = tracked_manifest(repo.path(), &original_path);
 
         // Create symlink at original location
+        let local_file = repo.path().join("myfile");
         symlink(local_file.canonicalize().unwrap(), &original_path).unwrap();
 
-        // Remove using test helper with absolute paths
-        remove_tracked_file(&local_file, &original_path).unwrap();
+        RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("myfile"),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
 
         // Original is now a regular file
         assert!(original_path.exists());
@@ -111,6 +227,30 @@ mod tests {
         );
         // Local file is gone
         assert!(!local_file.exists());
+        assert!(!manifest.contains(Path::new("myfile")));
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let repo = TempDir::new().unwrap();
+        let original_dir = TempDir::new().unwrap();
+        let original_path = original_dir.path().join("myfile");
+        let mut manifest = tracked_manifest(repo.path(), &original_path);
+        let local_file = repo.path().join("myfile");
+        symlink(local_file.canonicalize().unwrap(), &original_path).unwrap();
+
+        let mut ctx = Context::new(repo.path());
+        ctx.dry_run = true;
+        RemoveCommand::remove_from_manifest(&mut manifest, Path::new("myfile"), &ctx).unwrap();
+
+        assert!(local_file.exists());
+        assert!(
+            original_path
+                .symlink_metadata()
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
     }
 
     #[test]
@@ -118,13 +258,178 @@ mod tests {
         let repo = TempDir::new().unwrap();
         let target_dir = TempDir::new().unwrap();
         let target_path = target_dir.path().join("myfile");
+        let mut manifest = tracked_manifest(repo.path(), &target_path);
 
-        // Create local file and regular file (not symlink) at target
-        let local_file = repo.path().join("myfile");
-        fs::write(&local_file, "local").unwrap();
+        // Create regular file (not symlink) at target
         fs::write(&target_path, "blocking").unwrap();
 
-        let result = remove_tracked_file(&local_file, &target_path);
+        let result = RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("myfile"),
+            &Context::new(repo.path()),
+        );
         assert!(matches!(result, Err(Error::NotASymlink(_))));
     }
+
+    #[test]
+    fn returns_error_if_symlink_points_elsewhere() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target_path = target_dir.path().join("myfile");
+        let mut manifest = tracked_manifest(repo.path(), &target_path);
+        let elsewhere = target_dir.path().join("elsewhere");
+        fs::write(&elsewhere, "other").unwrap();
+        symlink(&elsewhere, &target_path).unwrap();
+
+        let result = RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("myfile"),
+            &Context::new(repo.path()),
+        );
+
+        assert!(matches!(result, Err(Error::ForeignSymlink(_, _))));
+        assert!(manifest.contains(Path::new("myfile")));
+        assert_eq!(fs::read_link(&target_path).unwrap(), elsewhere);
+        assert!(repo.path().join("myfile").exists());
+    }
+
+    #[test]
+    fn restores_unfolded_directory() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("fish");
+        let local = repo.path().join("fish");
+        fs::create_dir_all(local.join("functions")).unwrap();
+        fs::create_dir_all(target.join("functions")).unwrap();
+        for file in ["config.fish", "functions/ls.fish"] {
+            fs::write(local.join(file), "content").unwrap();
+            symlink(local.join(file), target.join(file)).unwrap();
+        }
+        fs::write(target.join("fish_variables"), "untracked").unwrap();
+
+        let mut manifest = Manifest::parse(&format!(
+            "[fish]\ntarget = {:?}\ndir = \"unfold\"\n",
+            target
+        ))
+        .unwrap();
+        RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("fish"),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
+
+        assert!(
+            target
+                .join("functions/ls.fish")
+                .symlink_metadata()
+                .unwrap()
+                .is_file()
+        );
+        assert!(
+            target
+                .join("config.fish")
+                .symlink_metadata()
+                .unwrap()
+                .is_file()
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("fish_variables")).unwrap(),
+            "untracked"
+        );
+        assert!(!local.exists());
+        assert!(!manifest.contains(Path::new("fish")));
+    }
+
+    #[test]
+    fn restores_copied_file() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        fs::write(repo.path().join("myfile"), "content").unwrap();
+        fs::write(&target, "content").unwrap();
+        let manifest_content =
+            format!("version = 2\n[files.myfile]\ntarget = {target:?}\nmode = \"copy\"\n");
+
+        let mut manifest = Manifest::parse(&manifest_content).unwrap();
+        let ctx = Context::new(repo.path());
+        let mut state = State::default();
+        state.record_copy(target.clone(), content_hash(&target).unwrap());
+        state.save_to(&ctx.state_path()).unwrap();
+        RemoveCommand::remove_from_manifest(&mut manifest, Path::new("myfile"), &ctx).unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        assert!(!repo.path().join("myfile").exists());
+        assert_eq!(
+            State::load_from(&ctx.state_path()).unwrap(),
+            State::default()
+        );
+
+        // An edited copy is kept rather than replaced
+        fs::write(repo.path().join("myfile"), "content").unwrap();
+        fs::write(&target, "edited").unwrap();
+        let mut manifest = Manifest::parse(&manifest_content).unwrap();
+        let result = RemoveCommand::remove_from_manifest(&mut manifest, Path::new("myfile"), &ctx);
+        assert!(matches!(result, Err(Error::ModifiedCopy(_))));
+    }
+
+    #[test]
+    fn restores_hard_linked_file() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        fs::write(repo.path().join("myfile"), "content").unwrap();
+        fs::hard_link(repo.path().join("myfile"), &target).unwrap();
+
+        let mut manifest = Manifest::parse(&format!(
+            "version = 2\n[files.myfile]\ntarget = {target:?}\nmode = \"hardlink\"\n"
+        ))
+        .unwrap();
+        RemoveCommand::remove_from_manifest(
+            &mut manifest,
+            Path::new("myfile"),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        assert!(!repo.path().join("myfile").exists());
+    }
+
+    #[test]
+    fn rolls_back_when_any_step_fails() {
+        for fail_at in 0..3 {
+            let repo = TempDir::new().unwrap();
+            let original_dir = TempDir::new().unwrap();
+            let original_path = original_dir.path().join("myfile");
+            let mut manifest = tracked_manifest(repo.path(), &original_path);
+            let local_file = repo.path().join("myfile");
+            symlink(local_file.canonicalize().unwrap(), &original_path).unwrap();
+
+            let ctx = Context::new(repo.path());
+            if fail_at >= 2 {
+                // A directory in place of the manifest makes saving fail
+                fs::create_dir(repo.path().join(MANIFEST_FILE)).unwrap();
+                fs::write(repo.path().join(MANIFEST_FILE).join("keep"), "").unwrap();
+            }
+            let result = RemoveCommand::remove_from_manifest_with(
+                &mut manifest,
+                Path::new("myfile"),
+                &ctx,
+                plan::fail_at(fail_at),
+            );
+
+            assert!(result.is_err(), "step {fail_at}");
+            assert_eq!(fs::read_to_string(&local_file).unwrap(), "content");
+            assert!(
+                original_path
+                    .symlink_metadata()
+                    .unwrap()
+                    .file_type()
+                    .is_symlink()
+            );
+            assert_eq!(fs::read_dir(original_dir.path()).unwrap().count(), 1);
+            assert!(manifest.contains(Path::new("myfile")));
+        }
+    }
 }
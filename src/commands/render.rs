@@ -0,0 +1,72 @@
+use std::path::{Path, PathBuf};
+
+use crate::commands::Command;
+use crate::context::Context;
+use crate::entry::LinkMode;
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::template::render_file;
+
+pub struct RenderCommand {
+    file_path: PathBuf,
+}
+
+impl RenderCommand {
+    pub fn new(file_path: PathBuf) -> Self {
+        Self { file_path }
+    }
+
+    /// What the template entry `file_path` renders to on this machine.
+    pub fn render(manifest: &Manifest, file_path: &Path, ctx: &Context) -> Result<String> {
+        let entry = manifest
+            .entry(file_path)
+            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
+        if entry.mode != LinkMode::Template {
+            return Err(Error::NotATemplate(file_path.to_path_buf()));
+        }
+        render_file(&ctx.local(file_path), &ctx.vars(manifest))
+    }
+}
+
+impl Command for RenderCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let file_path = ctx.resolve(&manifest, &self.file_path)?;
+        print!("{}", Self::render(&manifest, &file_path, ctx)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Entry;
+    use crate::facts::FakeFacts;
+    use std::fs;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn renders_template_entries_only() {
+        let repo = TempDir::new().unwrap();
+        let mut ctx = Context::new(repo.path());
+        ctx.facts = Rc::new(FakeFacts {
+            hostname: Some("laptop".into()),
+            ..FakeFacts::default()
+        });
+        fs::write(repo.path().join("gitconfig"), "# {{ hostname }}\n").unwrap();
+        fs::write(repo.path().join("vimrc"), "").unwrap();
+        let mut manifest = Manifest::empty();
+        let template = Entry {
+            mode: LinkMode::Template,
+            ..Entry::new("~/.gitconfig".into())
+        };
+        manifest.insert_entry("gitconfig".into(), template);
+        manifest.insert_entry("vimrc".into(), Entry::new("~/.vimrc".into()));
+
+        let rendered = RenderCommand::render(&manifest, Path::new("gitconfig"), &ctx).unwrap();
+        assert_eq!(rendered, "# laptop\n");
+        let result = RenderCommand::render(&manifest, Path::new("vimrc"), &ctx);
+        assert!(matches!(result, Err(Error::NotATemplate(_))));
+    }
+}
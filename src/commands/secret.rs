@@ -0,0 +1,228 @@
+use std::ffi::OsString;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command as Process;
+
+use crate::commands::Command;
+use crate::context::Context;
+use crate::entry::LinkMode;
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::secret::generate_key_file;
+use crate::transfer::write_atomic;
+
+/// Decrypt an encrypted entry into a private temporary file, open it in
+/// the user's editor and re-encrypt the result.
+pub struct SecretEditCommand {
+    file_path: PathBuf,
+}
+
+impl SecretEditCommand {
+    pub fn new(file_path: PathBuf) -> Self {
+        Self { file_path }
+    }
+
+    /// Run `edit` on a decrypted copy of `file_path` and store the result.
+    ///
+    /// Returns whether the secret changed. The plaintext lives in a private
+    /// directory, together with any swap or backup files the editor makes,
+    /// and is removed again whether or not editing succeeds.
+    pub fn edit_secret(
+        manifest: &Manifest,
+        file_path: &Path,
+        ctx: &Context,
+        edit: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<bool> {
+        let entry = manifest
+            .entry(file_path)
+            .ok_or_else(|| Error::NotFound(file_path.to_path_buf()))?;
+        if entry.mode != LinkMode::Encrypted {
+            return Err(Error::NotASecret(file_path.to_path_buf()));
+        }
+
+        let local = ctx.local(file_path);
+        let key = ctx.key()?;
+        let plaintext = key.decrypt_file(&local)?;
+
+        let scratch_dir = scratch_dir()?;
+        let scratch = scratch_dir.join(file_path.file_name().unwrap_or_default());
+        let edited = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&scratch)
+            .and_then(|mut file| file.write_all(&plaintext))
+            .map_err(Error::from)
+            .and_then(|_| edit(&scratch))
+            .and_then(|_| Ok(fs::read(&scratch)?));
+        fs::remove_dir_all(&scratch_dir)?;
+        let edited = edited?;
+
+        if edited == plaintext {
+            return Ok(false);
+        }
+        if !ctx.dry_run {
+            write_atomic(&local, key.encrypt(&edited)?)?;
+        }
+        Ok(true)
+    }
+}
+
+impl Command for SecretEditCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let file_path = ctx.resolve(&manifest, &self.file_path)?;
+        let changed = Self::edit_secret(&manifest, &file_path, ctx, open_editor)?;
+
+        if !changed {
+            println!("{} unchanged", file_path.display());
+        } else if ctx.dry_run {
+            println!("Would re-encrypt {}", file_path.display());
+        } else {
+            println!(
+                "Re-encrypted {} (run `dot sync` to deploy it)",
+                file_path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Create a new age key file for `--key-file`.
+pub struct SecretKeygenCommand {
+    path: PathBuf,
+}
+
+impl SecretKeygenCommand {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Command for SecretKeygenCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        if self.path.exists() {
+            return Err(Error::AlreadyExists(self.path));
+        }
+        if ctx.dry_run {
+            println!("Would create {}", self.path.display());
+            return Ok(());
+        }
+        let public = generate_key_file(&self.path)?;
+        println!("Created {} (public key {public})", self.path.display());
+        Ok(())
+    }
+}
+
+/// Open `path` in `$VISUAL` or `$EDITOR`, falling back to `vi`. The editor
+/// is run through the shell so it may include arguments.
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var_os("VISUAL")
+        .or_else(|| std::env::var_os("EDITOR"))
+        .unwrap_or_else(|| OsString::from("vi"));
+    let mut script = editor;
+    script.push(" \"$1\"");
+
+    let status = Process::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("sh")
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(Error::EditorFailed(status));
+    }
+    Ok(())
+}
+
+/// Create a new temporary directory that only the user can enter, for a
+/// plaintext file named after the entry so editors pick a fitting syntax.
+fn scratch_dir() -> Result<PathBuf> {
+    let mut counter = 0;
+    loop {
+        let mut name = format!("dot-secret-{}", std::process::id());
+        if counter > 0 {
+            name.push_str(&format!(".{counter}"));
+        }
+        let dir = std::env::temp_dir().join(name);
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {
+                // An unusual umask could take the owner's own access away
+                fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+                return Ok(dir);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Entry;
+    use crate::secret::Key;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    fn secret_repo() -> (TempDir, Context, Manifest) {
+        let repo = TempDir::new().unwrap();
+        let ctx = Context::new(repo.path());
+        ctx.key
+            .set(Rc::new(Key::Passphrase("pass".to_string().into())))
+            .unwrap();
+        let ciphertext = ctx.key().unwrap().encrypt(b"token = old\n").unwrap();
+        fs::write(repo.path().join("netrc"), ciphertext).unwrap();
+
+        let mut manifest = Manifest::empty();
+        let entry = Entry {
+            mode: LinkMode::Encrypted,
+            ..Entry::new("~/.netrc".into())
+        };
+        manifest.insert_entry("netrc".into(), entry);
+        (repo, ctx, manifest)
+    }
+
+    #[test]
+    fn edits_and_reencrypts() {
+        let (repo, ctx, manifest) = secret_repo();
+        let mut scratch = PathBuf::new();
+
+        let changed = SecretEditCommand::edit_secret(&manifest, Path::new("netrc"), &ctx, |path| {
+            assert_eq!(fs::read_to_string(path).unwrap(), "token = old\n");
+            let dir = path.parent().unwrap();
+            assert_eq!(dir.metadata()?.permissions().mode() & 0o777, 0o700);
+            scratch = path.to_path_buf();
+            // Editors may leave swap files next to the one they edit
+            fs::write(dir.join(".netrc.swp"), "token = new")?;
+            Ok(fs::write(path, "token = new\n")?)
+        })
+        .unwrap();
+
+        assert!(changed);
+        assert!(!scratch.parent().unwrap().exists());
+        let stored = fs::read_to_string(repo.path().join("netrc")).unwrap();
+        assert!(!stored.contains("token"));
+        let decrypted = ctx.key().unwrap().decrypt_file(&repo.path().join("netrc"));
+        assert_eq!(decrypted.unwrap(), b"token = new\n");
+    }
+
+    #[test]
+    fn failed_edit_keeps_secret_and_removes_plaintext() {
+        let (repo, ctx, manifest) = secret_repo();
+        let before = fs::read(repo.path().join("netrc")).unwrap();
+        let mut scratch = PathBuf::new();
+
+        let result = SecretEditCommand::edit_secret(&manifest, Path::new("netrc"), &ctx, |path| {
+            scratch = path.to_path_buf();
+            fs::write(path, "half-written")?;
+            Err(Error::NoKey)
+        });
+
+        assert!(result.is_err());
+        assert!(!scratch.parent().unwrap().exists());
+        assert_eq!(fs::read(repo.path().join("netrc")).unwrap(), before);
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::PathBuf;
+
+use crate::commands::Command;
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::link::LinkState;
+use crate::manifest::Manifest;
+use crate::state::State;
+
+pub struct StatusCommand;
+
+impl StatusCommand {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Core logic separated for testing with a custom repository root
+    pub fn status_manifest(manifest: &Manifest, ctx: &Context) -> Result<Vec<EntryStatus>> {
+        let mut statuses = Vec::new();
+
+        let state = State::load_from(&ctx.state_path())?;
+        let vars = ctx.vars(manifest);
+
+        for link in ctx.links(manifest)? {
+            statuses.push(EntryStatus {
+                state: ctx.inspect(&link, &state, &vars)?,
+                local: link.file,
+                symlink: link.target,
+            });
+        }
+
+        Ok(statuses)
+    }
+}
+
+impl Default for StatusCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EntryStatus {
+    pub local: PathBuf,
+    pub symlink: PathBuf,
+    pub state: LinkState,
+}
+
+impl Command for StatusCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let statuses = Self::status_manifest(&manifest, ctx)?;
+
+        for status in &statuses {
+            let line = format!(
+                "{:<17} {} -> {}",
+                status.state,
+                status.local.display(),
+                status.symlink.display()
+            );
+            match &status.state {
+                LinkState::WrongTarget(dest) | LinkState::Unmanaged(dest) => {
+                    println!("{line} (points to {})", dest.display())
+                }
+                LinkState::WrongPermissions(actual) => println!("{line} (is {actual:04o})"),
+                _ => println!("{line}"),
+            }
+        }
+
+        let out_of_sync = statuses.iter().filter(|s| !s.state.is_ok()).count();
+        if out_of_sync > 0 {
+            return Err(Error::OutOfSync(out_of_sync));
+        }
+
+        println!("All {} entries linked", statuses.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn reports_state_of_every_entry() {
+        let repo = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::write(repo.path().join("linked"), "").unwrap();
+        fs::write(repo.path().join("missing"), "").unwrap();
+        fs::write(repo.path().join("conflict"), "").unwrap();
+
+        let linked = home.path().join("linked");
+        symlink(repo.path().join("linked"), &linked).unwrap();
+        let conflict = home.path().join("conflict");
+        fs::write(&conflict, "blocking").unwrap();
+
+        let mut manifest = Manifest::empty();
+        manifest.insert("linked".into(), &linked).unwrap();
+        manifest
+            .insert("missing".into(), &home.path().join("missing"))
+            .unwrap();
+        manifest.insert("conflict".into(), &conflict).unwrap();
+        manifest
+            .insert("gone".into(), &home.path().join("gone"))
+            .unwrap();
+
+        let statuses =
+            StatusCommand::status_manifest(&manifest, &Context::new(repo.path())).unwrap();
+        let states: Vec<_> = statuses
+            .iter()
+            .map(|s| (s.local.to_str().unwrap(), &s.state))
+            .collect();
+
+        assert_eq!(
+            states,
+            [
+                ("conflict", &LinkState::Conflict),
+                ("gone", &LinkState::Broken),
+                ("linked", &LinkState::Linked),
+                ("missing", &LinkState::Missing),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_manifest_has_no_entries() {
+        let repo = TempDir::new().unwrap();
+        let statuses =
+            StatusCommand::status_manifest(&Manifest::empty(), &Context::new(repo.path())).unwrap();
+        assert!(statuses.is_empty());
+    }
+}
@@ -1,96 +1,542 @@
 use std::fs;
-use std::os::unix::fs::symlink;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
 use crate::commands::Command;
+use crate::conflict::{self, ConflictStrategy};
+use crate::context::{Context, Link};
+use crate::entry::LinkMode;
 use crate::error::{Error, Result};
+use crate::link::{LinkState, points_into, symlink_source};
 use crate::manifest::Manifest;
+use crate::plan::{Action, Plan};
+use crate::state::{State, bytes_hash, content_hash};
+use crate::template::{Vars, render_file};
 
-pub struct SyncCommand;
+pub struct SyncCommand {
+    options: SyncOptions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SyncOptions {
+    /// Replace symlinks that point somewhere other than the repository file
+    pub relink: bool,
+    /// What to do when a regular file occupies a target
+    pub on_conflict: ConflictStrategy,
+}
 
 impl SyncCommand {
-    pub fn new() -> Self {
-        Self
+    pub fn new(options: SyncOptions) -> Self {
+        Self { options }
     }
 
-    /// Result of sync operation for testability
-    pub fn sync_manifest(manifest: &Manifest) -> Result<SyncResult> {
+    /// Work out the filesystem changes needed to bring every entry up to date.
+    ///
+    /// Copied targets are checked against the hashes in `state`, which is
+    /// updated with the hashes they will have once the plan is applied.
+    pub fn plan_sync(
+        manifest: &Manifest,
+        options: &SyncOptions,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<(SyncResult, Plan)> {
         let mut result = SyncResult::default();
+        let mut plan = Plan::new();
+        let vars = ctx.vars(manifest);
+
+        for link in ctx.links(manifest)? {
+            Self::plan_link(link, options, &vars, state, &mut plan, &mut result, ctx)?;
+        }
 
-        for (local_path, symlink_result) in manifest.iter() {
-            let symlink_path = symlink_result?;
+        Self::plan_prune(manifest, state, &mut plan, &mut result, ctx)?;
 
-            if !local_path.exists() {
-                return Err(Error::NotFound(local_path.to_path_buf()));
-            }
+        Ok((result, plan))
+    }
+
+    /// Plan what brings a single link up to date, recording it in `result`.
+    pub fn plan_link(
+        link: Link,
+        options: &SyncOptions,
+        vars: &Vars,
+        state: &mut State,
+        plan: &mut Plan,
+        result: &mut SyncResult,
+        ctx: &Context,
+    ) -> Result<()> {
+        let requested = link.mode;
+        let link = Link {
+            mode: ctx.deploy_mode(&link)?,
+            ..link
+        };
+        let local = ctx.local(&link.file);
+        let symlink_path = link.target.clone();
+        let planned = plan.actions().len();
+
+        // Symlinks, hard links and copies all take their permissions
+        // from the repository file, so fix it before deploying
+        if let Some(mode) = link.permissions
+            && !link.skipped
+            && matches!(
+                link.mode,
+                LinkMode::Symlink | LinkMode::Hardlink | LinkMode::Copy
+            )
+            && local.exists()
+            && local.metadata()?.permissions().mode() & 0o7777 != mode
+        {
+            plan.push(Action::SetPermissions {
+                path: local.clone(),
+                mode,
+            });
+            result.permissions.push((local.clone(), mode));
+        }
 
-            if !symlink_path.exists() {
-                // Create parent directories if needed
+        match ctx.inspect(&link, state, vars)? {
+            // Links made before they were recorded can be pruned too
+            LinkState::Linked if link.mode == LinkMode::Symlink => {
+                state.record_link(link.target.clone());
+            }
+            LinkState::Linked => {}
+            LinkState::WrongPermissions(_) => {
+                let path = ctx.permissions_path(&link)?;
+                if let Some(mode) = link.permissions
+                    && path != local
+                {
+                    plan.push(Action::SetPermissions {
+                        path: path.clone(),
+                        mode,
+                    });
+                    result.permissions.push((path, mode));
+                }
+            }
+            LinkState::Skipped => result.skipped.push(link.file),
+            LinkState::Broken => return Err(Error::NotFound(link.file)),
+            LinkState::Missing => {
+                // Create parent directories if needed, as private as
+                // the file they will hold
                 if let Some(parent) = symlink_path.parent() {
-                    fs::create_dir_all(parent)?;
+                    plan.ensure_dir_with_mode(parent, link.permissions.map(dir_mode));
                 }
 
-                let canonical = local_path.canonicalize()?;
-                symlink(&canonical, &symlink_path)?;
+                plan.push(Self::deploy(&link, &local, &local, vars, state, ctx)?);
 
                 result.created.push(CreatedSymlink {
-                    local: local_path.to_path_buf(),
+                    local: link.file.clone(),
+                    symlink: symlink_path,
+                    mode: link.mode,
+                });
+            }
+            LinkState::Outdated => {
+                // The target still holds what was last deployed, so
+                // replacing it loses nothing
+                plan.push(Action::Remove(symlink_path.clone()));
+                plan.push(Self::deploy(&link, &local, &local, vars, state, ctx)?);
+
+                result.updated.push(CreatedSymlink {
+                    local: link.file.clone(),
+                    symlink: symlink_path,
+                    mode: link.mode,
+                });
+            }
+            LinkState::Conflict | LinkState::Modified => {
+                // A dry run never prompts; it reports the conflict as
+                // unresolved instead
+                let strategy = if ctx.dry_run {
+                    options.on_conflict
+                } else {
+                    options.on_conflict.resolve(&symlink_path)?
+                };
+                let mut content = local.clone();
+                let backup = match strategy {
+                    ConflictStrategy::Skip | ConflictStrategy::Ask => {
+                        result.conflicts.push(symlink_path);
+                        return Ok(());
+                    }
+                    ConflictStrategy::Backup => {
+                        let backup = conflict::backup_path(&symlink_path);
+                        plan.push(Action::Move {
+                            from: symlink_path.clone(),
+                            to: backup.clone(),
+                        });
+                        Some(backup)
+                    }
+                    ConflictStrategy::Overwrite => {
+                        plan.push(Action::Remove(symlink_path.clone()));
+                        None
+                    }
+                    ConflictStrategy::Adopt if link.mode == LinkMode::Template => {
+                        return Err(Error::AdoptTemplate(symlink_path));
+                    }
+                    ConflictStrategy::Adopt if link.mode == LinkMode::Encrypted => {
+                        // Re-encrypt the edited secret; the plaintext
+                        // never enters the repository
+                        let plaintext = fs::read(&symlink_path)?;
+                        let ciphertext = ctx.key()?.encrypt(&plaintext)?;
+                        state.record_copy(symlink_path.clone(), bytes_hash(&plaintext));
+                        state
+                            .record_source(symlink_path.clone(), bytes_hash(ciphertext.as_bytes()));
+                        plan.push(Action::Remove(local.clone()));
+                        plan.push(Action::Write {
+                            path: local.clone(),
+                            content: ciphertext.into_bytes(),
+                            mode: None,
+                        });
+                        result.resolved.push(ResolvedConflict {
+                            local: link.file.clone(),
+                            symlink: symlink_path,
+                            strategy,
+                            backup: None,
+                            mode: link.mode,
+                        });
+                        return Ok(());
+                    }
+                    ConflictStrategy::Adopt => {
+                        plan.push(Action::Remove(local.clone()));
+                        plan.push(Action::Move {
+                            from: symlink_path.clone(),
+                            to: local.clone(),
+                        });
+                        // The deployed copy will hold the adopted file
+                        content = symlink_path.clone();
+                        None
+                    }
+                };
+
+                plan.push(Self::deploy(&link, &local, &content, vars, state, ctx)?);
+
+                result.resolved.push(ResolvedConflict {
+                    local: link.file.clone(),
                     symlink: symlink_path,
+                    strategy,
+                    backup,
+                    mode: link.mode,
                 });
-            } else {
-                let metadata = symlink_path.symlink_metadata()?;
-                if !metadata.file_type().is_symlink() {
-                    result.conflicts.push(symlink_path);
+            }
+            LinkState::WrongTarget(points_to) | LinkState::Unmanaged(points_to) => {
+                if options.relink {
+                    plan.push(Action::Remove(symlink_path.clone()));
+                    plan.push(Self::deploy(&link, &local, &local, vars, state, ctx)?);
+
+                    result.relinked.push(CreatedSymlink {
+                        local: link.file.clone(),
+                        symlink: symlink_path,
+                        mode: link.mode,
+                    });
+                } else {
+                    result.stale.push(StaleSymlink {
+                        symlink: symlink_path,
+                        points_to,
+                    });
                 }
             }
         }
 
+        // Only warn about a fallback when the link is actually deployed
+        if link.mode != requested && plan.actions().len() > planned {
+            result.fallbacks.push(link.target);
+        }
+        Ok(())
+    }
+
+    /// Plan to remove symlinks recorded in `state` whose entry is no longer
+    /// in the manifest. Links that were replaced or now point outside the
+    /// repository are forgotten without touching them.
+    fn plan_prune(
+        manifest: &Manifest,
+        state: &mut State,
+        plan: &mut Plan,
+        result: &mut SyncResult,
+        ctx: &Context,
+    ) -> Result<()> {
+        let mut current = Vec::new();
+        for (_, target) in ctx.targets(manifest) {
+            current.push(target?);
+        }
+
+        let recorded: Vec<PathBuf> = state.links().iter().cloned().collect();
+        for link in recorded {
+            // Files of unfolded directories live below their entry's target
+            if current.iter().any(|target| link.starts_with(target)) {
+                continue;
+            }
+            state.forget_link(&link);
+
+            let is_symlink = link.symlink_metadata().is_ok_and(|m| m.is_symlink());
+            if is_symlink && points_into(&link, &ctx.repo)? {
+                plan.push(Action::Remove(link.clone()));
+                result.pruned.push(link);
+            }
+        }
+        Ok(())
+    }
+
+    /// The action that deploys `local` to the link's target.
+    ///
+    /// `content` is the file whose contents the target will end up with,
+    /// which differs from `local` only when adopting; its hash is recorded
+    /// for copies. Templates are rendered with `vars` and secrets decrypted
+    /// with the context's key, recording the hash of the output instead.
+    fn deploy(
+        link: &Link,
+        local: &Path,
+        content: &Path,
+        vars: &Vars,
+        state: &mut State,
+        ctx: &Context,
+    ) -> Result<Action> {
+        match link.mode {
+            LinkMode::Symlink => {
+                state.record_link(link.target.clone());
+                Ok(Action::Symlink {
+                    original: symlink_source(local, &link.target, link.relative)?,
+                    link: link.target.clone(),
+                })
+            }
+            LinkMode::Copy => {
+                state.record_copy(link.target.clone(), content_hash(content)?);
+                Ok(Action::Copy {
+                    from: local.to_path_buf(),
+                    to: link.target.clone(),
+                })
+            }
+            LinkMode::Hardlink => Ok(Action::Hardlink {
+                original: local.to_path_buf(),
+                link: link.target.clone(),
+            }),
+            LinkMode::Template => {
+                let rendered = render_file(local, vars)?;
+                state.record_copy(link.target.clone(), bytes_hash(rendered.as_bytes()));
+                Ok(Action::Write {
+                    path: link.target.clone(),
+                    content: rendered.into_bytes(),
+                    mode: link.permissions,
+                })
+            }
+            LinkMode::Encrypted => {
+                let plaintext = ctx.key()?.decrypt_file(local)?;
+                state.record_copy(link.target.clone(), bytes_hash(&plaintext));
+                state.record_source(link.target.clone(), content_hash(local)?);
+                Ok(Action::Write {
+                    path: link.target.clone(),
+                    content: plaintext,
+                    // Never looser than 0600, even for a hand-made link
+                    mode: Some(link.permissions.unwrap_or(0o600) & 0o600),
+                })
+            }
+        }
+    }
+
+    /// Result of sync operation for testability
+    ///
+    /// Runs as a transaction: if an action fails or the state file cannot
+    /// be saved, the completed actions are undone.
+    pub fn sync_manifest(
+        manifest: &Manifest,
+        options: &SyncOptions,
+        ctx: &Context,
+    ) -> Result<SyncResult> {
+        let loaded = State::load_from(&ctx.state_path())?;
+        let mut state = loaded.clone();
+        let (result, plan) = Self::plan_sync(manifest, options, &mut state, ctx)?;
+
+        let transaction = ctx.begin(&plan)?;
+        if !ctx.dry_run
+            && state != loaded
+            && let Err(e) = state.save_to(&ctx.state_path())
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
         Ok(result)
     }
 }
 
 impl Default for SyncCommand {
     fn default() -> Self {
-        Self::new()
+        Self::new(SyncOptions::default())
     }
 }
 
 #[derive(Debug, Default, PartialEq)]
 pub struct SyncResult {
     pub created: Vec<CreatedSymlink>,
+    /// Copies refreshed because the repository file changed
+    pub updated: Vec<CreatedSymlink>,
+    pub relinked: Vec<CreatedSymlink>,
+    pub stale: Vec<StaleSymlink>,
+    pub resolved: Vec<ResolvedConflict>,
     pub conflicts: Vec<PathBuf>,
+    /// Hard-link targets copied instead because they are on another
+    /// filesystem than the repository
+    pub fallbacks: Vec<PathBuf>,
+    /// Entries left alone because their condition fails on this machine
+    pub skipped: Vec<PathBuf>,
+    /// Files whose permission bits were set to the manifest's
+    pub permissions: Vec<(PathBuf, u32)>,
+    /// Symlinks removed because their entry left the manifest
+    pub pruned: Vec<PathBuf>,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct CreatedSymlink {
-    pub local: PathBuf,
-    pub symlink: PathBuf,
-}
+impl SyncResult {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.updated.is_empty()
+            && self.relinked.is_empty()
+            && self.stale.is_empty()
+            && self.resolved.is_empty()
+            && self.conflicts.is_empty()
+            && self.permissions.is_empty()
+            && self.pruned.is_empty()
+    }
 
-impl Command for SyncCommand {
-    fn execute(self) -> Result<()> {
-        let manifest = Manifest::load()?;
-        let result = Self::sync_manifest(&manifest)?;
-
-        for created in &result.created {
-            println!(
-                "Created symlink: {} -> {}",
-                created.symlink.display(),
-                created.local.display()
+    /// Report warnings and, unless `dry_run`, every change made.
+    pub fn print(&self, dry_run: bool) {
+        for conflict in &self.conflicts {
+            eprintln!(
+                "Warning: {} exists but is not a symlink",
+                conflict.display()
             );
         }
 
-        for conflict in &result.conflicts {
+        for fallback in &self.fallbacks {
             eprintln!(
-                "Warning: {} exists but is not a symlink",
-                conflict.display()
+                "Warning: {} is on a different filesystem than the repository; copying instead of hard-linking",
+                fallback.display()
+            );
+        }
+
+        for stale in &self.stale {
+            eprintln!(
+                "Warning: {} points to {} (use --relink to replace it)",
+                stale.symlink.display(),
+                stale.points_to.display()
             );
         }
 
-        if result.created.is_empty() && result.conflicts.is_empty() {
+        for skipped in &self.skipped {
+            println!("{} skipped (condition)", skipped.display());
+        }
+
+        if self.is_empty() {
             println!("Up to date");
         }
 
+        if dry_run {
+            return;
+        }
+
+        for created in &self.created {
+            created.print(created_verb(created.mode));
+        }
+
+        for updated in &self.updated {
+            updated.print("Updated");
+        }
+
+        for relinked in &self.relinked {
+            relinked.print("Relinked");
+        }
+
+        for resolved in &self.resolved {
+            match (&resolved.strategy, &resolved.backup) {
+                (ConflictStrategy::Adopt, _) => println!(
+                    "Adopted {} into {}",
+                    resolved.symlink.display(),
+                    resolved.local.display()
+                ),
+                (_, Some(backup)) => println!(
+                    "Backed up {} to {}",
+                    resolved.symlink.display(),
+                    backup.display()
+                ),
+                _ => println!("Overwrote {}", resolved.symlink.display()),
+            }
+            // An adopted secret stays in place, only its ciphertext changed
+            if resolved.strategy == ConflictStrategy::Adopt && resolved.mode == LinkMode::Encrypted
+            {
+                continue;
+            }
+            print_deployed(
+                created_verb(resolved.mode),
+                &resolved.symlink,
+                &resolved.local,
+                resolved.mode,
+            );
+        }
+
+        for (path, mode) in &self.permissions {
+            println!("Set permissions of {} to {mode:04o}", path.display());
+        }
+
+        for pruned in &self.pruned {
+            println!("Removed stale link: {}", pruned.display());
+        }
+    }
+}
+
+/// Permissions for a directory created to hold a file with `mode`: the
+/// file's bits, searchable wherever they are readable.
+fn dir_mode(mode: u32) -> u32 {
+    mode | (mode & 0o444) >> 2
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CreatedSymlink {
+    pub local: PathBuf,
+    pub symlink: PathBuf,
+    pub mode: LinkMode,
+}
+
+impl CreatedSymlink {
+    fn print(&self, verb: &str) {
+        print_deployed(verb, &self.symlink, &self.local, self.mode);
+    }
+}
+
+fn created_verb(mode: LinkMode) -> &'static str {
+    match mode {
+        LinkMode::Symlink => "Created symlink",
+        LinkMode::Copy => "Copied",
+        LinkMode::Hardlink => "Created hard link",
+        LinkMode::Template => "Rendered",
+        LinkMode::Encrypted => "Decrypted",
+    }
+}
+
+/// Print a deployed target, as a link or a copy depending on its mode.
+fn print_deployed(verb: &str, target: &Path, local: &Path, mode: LinkMode) {
+    match mode {
+        LinkMode::Symlink => println!("{verb}: {} -> {}", target.display(), local.display()),
+        LinkMode::Copy => println!("{verb}: {} (copy of {})", target.display(), local.display()),
+        LinkMode::Hardlink => println!("{verb}: {} => {}", target.display(), local.display()),
+        LinkMode::Template | LinkMode::Encrypted => {
+            println!("{verb}: {} (from {})", target.display(), local.display())
+        }
+    }
+}
+
+/// A conflicting file that was dealt with according to a `ConflictStrategy`
+#[derive(Debug, PartialEq)]
+pub struct ResolvedConflict {
+    pub local: PathBuf,
+    pub symlink: PathBuf,
+    pub strategy: ConflictStrategy,
+    pub backup: Option<PathBuf>,
+    pub mode: LinkMode,
+}
+
+/// A symlink at a target that points somewhere other than the repository file
+#[derive(Debug, PartialEq)]
+pub struct StaleSymlink {
+    pub symlink: PathBuf,
+    pub points_to: PathBuf,
+}
+
+impl Command for SyncCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let result = Self::sync_manifest(&manifest, &self.options, ctx)?;
+        result.print(ctx.dry_run);
         Ok(())
     }
 }
@@ -98,51 +544,37 @@ impl Command for SyncCommand {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::StatusCommand;
+    use crate::condition::Condition;
+    use crate::entry::{DirMode, Entry};
+    use crate::facts::FakeFacts;
+    use crate::link::same_inode;
+    use crate::secret::Key;
+    use std::fs;
     use std::os::unix::fs::symlink as create_symlink;
-    use std::path::Path;
+    use std::rc::Rc;
     use tempfile::TempDir;
 
-    fn sync_entries(entries: &[(&Path, &Path)]) -> Result<SyncResult> {
-        let mut result = SyncResult::default();
-
-        for (local_path, symlink_path) in entries {
-            if !local_path.exists() {
-                return Err(Error::NotFound(local_path.to_path_buf()));
-            }
-
-            if !symlink_path.exists() {
-                if let Some(parent) = symlink_path.parent() {
-                    fs::create_dir_all(parent)?;
-                }
-
-                let canonical = local_path.canonicalize()?;
-                symlink(&canonical, symlink_path)?;
-
-                result.created.push(CreatedSymlink {
-                    local: local_path.to_path_buf(),
-                    symlink: symlink_path.to_path_buf(),
-                });
-            } else {
-                let metadata = symlink_path.symlink_metadata()?;
-                if !metadata.file_type().is_symlink() {
-                    result.conflicts.push(symlink_path.to_path_buf());
-                }
-            }
-        }
+    /// Test helper that syncs a single entry named "myfile" into `target`
+    fn sync_entry(repo: &Path, target: &Path, options: &SyncOptions) -> Result<SyncResult> {
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), target)?;
+        SyncCommand::sync_manifest(&manifest, options, &Context::new(repo))
+    }
 
-        Ok(result)
+    fn repo_with_file() -> TempDir {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join("myfile"), "content").unwrap();
+        repo
     }
 
     #[test]
     fn creates_missing_symlinks() {
-        let repo = TempDir::new().unwrap();
-        let local_file = repo.path().join("myfile");
-        fs::write(&local_file, "content").unwrap();
-
+        let repo = repo_with_file();
         let target_dir = TempDir::new().unwrap();
-        let symlink_path = target_dir.path().join("myfile");
+        let symlink_path = target_dir.path().join("nested/myfile");
 
-        let result = sync_entries(&[(&local_file, &symlink_path)]).unwrap();
+        let result = sync_entry(repo.path(), &symlink_path, &SyncOptions::default()).unwrap();
 
         assert_eq!(result.created.len(), 1);
         assert!(
@@ -156,39 +588,663 @@ mod tests {
 
     #[test]
     fn reports_conflicts() {
-        let repo = TempDir::new().unwrap();
-        let local_file = repo.path().join("myfile");
-        fs::write(&local_file, "content").unwrap();
-
+        let repo = repo_with_file();
         let target_dir = TempDir::new().unwrap();
         let conflict_path = target_dir.path().join("myfile");
         fs::write(&conflict_path, "blocking").unwrap();
 
-        let result = sync_entries(&[(&local_file, &conflict_path)]).unwrap();
+        let result = sync_entry(repo.path(), &conflict_path, &SyncOptions::default()).unwrap();
 
-        assert_eq!(result.conflicts.len(), 1);
-        assert_eq!(result.conflicts[0], conflict_path);
+        assert_eq!(result.conflicts, [conflict_path]);
     }
 
     #[test]
     fn returns_error_for_missing_local_file() {
-        let result = sync_entries(&[(Path::new("/nonexistent"), Path::new("/tmp/somewhere"))]);
+        let repo = TempDir::new().unwrap();
+        let result = sync_entry(
+            repo.path(),
+            Path::new("/tmp/somewhere"),
+            &SyncOptions::default(),
+        );
         assert!(matches!(result, Err(Error::NotFound(_))));
     }
 
     #[test]
     fn up_to_date_returns_empty_result() {
-        let repo = TempDir::new().unwrap();
-        let local_file = repo.path().join("myfile");
-        fs::write(&local_file, "content").unwrap();
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let symlink_path = target_dir.path().join("myfile");
+        create_symlink(
+            repo.path().join("myfile").canonicalize().unwrap(),
+            &symlink_path,
+        )
+        .unwrap();
+
+        let result = sync_entry(repo.path(), &symlink_path, &SyncOptions::default()).unwrap();
+
+        assert!(result.is_empty());
+    }
 
+    #[test]
+    fn reports_stale_symlinks() {
+        let repo = repo_with_file();
         let target_dir = TempDir::new().unwrap();
         let symlink_path = target_dir.path().join("myfile");
-        create_symlink(local_file.canonicalize().unwrap(), &symlink_path).unwrap();
+        let old_checkout = target_dir.path().join("old/myfile");
+        create_symlink(&old_checkout, &symlink_path).unwrap();
+
+        let result = sync_entry(repo.path(), &symlink_path, &SyncOptions::default()).unwrap();
+
+        assert_eq!(
+            result.stale,
+            [StaleSymlink {
+                symlink: symlink_path.clone(),
+                points_to: old_checkout.clone(),
+            }]
+        );
+        assert_eq!(fs::read_link(&symlink_path).unwrap(), old_checkout);
+    }
+
+    #[test]
+    fn relinks_stale_symlinks() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let symlink_path = target_dir.path().join("myfile");
+        let foreign = target_dir.path().join("foreign");
+        fs::write(&foreign, "foreign").unwrap();
+        create_symlink(&foreign, &symlink_path).unwrap();
+
+        let options = SyncOptions {
+            relink: true,
+            ..Default::default()
+        };
+        let result = sync_entry(repo.path(), &symlink_path, &options).unwrap();
+
+        assert_eq!(result.relinked.len(), 1);
+        assert!(result.stale.is_empty());
+        assert_eq!(fs::read_to_string(&symlink_path).unwrap(), "content");
+        assert_eq!(fs::read_to_string(&foreign).unwrap(), "foreign");
+    }
+
+    fn sync_conflict(strategy: ConflictStrategy) -> (TempDir, TempDir, SyncResult) {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let conflict_path = target_dir.path().join("myfile");
+        fs::write(&conflict_path, "existing").unwrap();
+
+        let options = SyncOptions {
+            on_conflict: strategy,
+            ..Default::default()
+        };
+        let result = sync_entry(repo.path(), &conflict_path, &options).unwrap();
+        (repo, target_dir, result)
+    }
+
+    #[test]
+    fn backup_strategy_keeps_existing_file() {
+        let (repo, target_dir, result) = sync_conflict(ConflictStrategy::Backup);
+
+        let backup = result.resolved[0].backup.clone().unwrap();
+        assert_eq!(fs::read_to_string(backup).unwrap(), "existing");
+        let target = target_dir.path().join("myfile");
+        assert_eq!(fs::read_to_string(target).unwrap(), "content");
+        assert_eq!(
+            fs::read_to_string(repo.path().join("myfile")).unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn overwrite_strategy_replaces_existing_file() {
+        let (_repo, target_dir, result) = sync_conflict(ConflictStrategy::Overwrite);
+
+        assert_eq!(result.resolved[0].backup, None);
+        let target = target_dir.path().join("myfile");
+        assert_eq!(fs::read_to_string(target).unwrap(), "content");
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn adopt_strategy_replaces_repository_copy() {
+        let (repo, target_dir, result) = sync_conflict(ConflictStrategy::Adopt);
+
+        assert_eq!(result.resolved[0].strategy, ConflictStrategy::Adopt);
+        assert_eq!(
+            fs::read_to_string(repo.path().join("myfile")).unwrap(),
+            "existing"
+        );
+        let target = target_dir.path().join("myfile");
+        assert!(target.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(target).unwrap(), "existing");
+    }
+
+    #[test]
+    fn dry_run_plans_without_linking() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let symlink_path = target_dir.path().join("nested/myfile");
+
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), &symlink_path).unwrap();
+        let mut ctx = Context::new(repo.path());
+        ctx.dry_run = true;
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.created.len(), 1);
+        assert!(!target_dir.path().join("nested").exists());
+    }
+
+    #[test]
+    fn dry_run_reports_conflicts_without_asking() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let conflict_path = target_dir.path().join("myfile");
+        fs::write(&conflict_path, "blocking").unwrap();
+
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), &conflict_path).unwrap();
+        let mut ctx = Context::new(repo.path());
+        ctx.dry_run = true;
+        let options = SyncOptions {
+            on_conflict: ConflictStrategy::Ask,
+            ..SyncOptions::default()
+        };
+        let (result, plan) =
+            SyncCommand::plan_sync(&manifest, &options, &mut State::default(), &ctx).unwrap();
+
+        assert_eq!(result.conflicts, [conflict_path]);
+        assert!(plan.actions().is_empty());
+    }
+
+    #[test]
+    fn plans_parent_directory_creation() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let symlink_path = target_dir.path().join("nested/myfile");
+
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), &symlink_path).unwrap();
+        let (_, plan) = SyncCommand::plan_sync(
+            &manifest,
+            &SyncOptions::default(),
+            &mut State::default(),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.actions(),
+            [
+                Action::CreateDir(target_dir.path().join("nested")),
+                Action::Symlink {
+                    original: repo.path().join("myfile").canonicalize().unwrap(),
+                    link: symlink_path,
+                },
+            ]
+        );
+    }
 
-        let result = sync_entries(&[(&local_file, &symlink_path)]).unwrap();
+    #[test]
+    fn deploys_under_target_root() {
+        let repo = repo_with_file();
+        let stage = TempDir::new().unwrap();
+        let manifest = Manifest::parse(r#""myfile" = "~/.myfile""#).unwrap();
+
+        let mut ctx = Context::new(repo.path());
+        ctx.home = Some("/home/user".into());
+        ctx.target_root = Some(stage.path().to_path_buf());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        let deployed = stage.path().join("home/user/.myfile");
+        assert_eq!(fs::read_to_string(deployed).unwrap(), "content");
+    }
+
+    #[test]
+    fn links_each_file_of_unfolded_directory() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("fish");
+        fs::create_dir_all(repo.path().join("fish/functions")).unwrap();
+        fs::write(repo.path().join("fish/config.fish"), "").unwrap();
+        fs::write(repo.path().join("fish/functions/ls.fish"), "").unwrap();
+        fs::write(repo.path().join("fish/notes.md"), "").unwrap();
+
+        let manifest = Manifest::parse(&format!(
+            "[fish]\ntarget = {:?}\ndir = \"unfold\"\ninclude = [\"**/*.fish\"]\n",
+            target
+        ))
+        .unwrap();
+        let result = SyncCommand::sync_manifest(
+            &manifest,
+            &SyncOptions::default(),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
+
+        assert_eq!(result.created.len(), 2);
+        assert!(target.symlink_metadata().unwrap().is_dir());
+        for file in ["config.fish", "functions/ls.fish"] {
+            let link = target.join(file);
+            assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        }
+        assert!(!target.join("notes.md").exists());
+    }
+
+    /// Manifest with "myfile" deployed as a copy at `target`
+    fn copy_manifest(target: &Path) -> Manifest {
+        Manifest::parse(&format!(
+            "version = 2\n[files.myfile]\ntarget = {target:?}\nmode = \"copy\"\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn copies_file_and_records_hash() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        let ctx = Context::new(repo.path());
+
+        let result =
+            SyncCommand::sync_manifest(&copy_manifest(&target), &SyncOptions::default(), &ctx)
+                .unwrap();
+
+        assert_eq!(result.created.len(), 1);
+        assert!(target.symlink_metadata().unwrap().is_file());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        let state = State::load_from(&ctx.state_path()).unwrap();
+        assert_eq!(
+            state.copy_hash(&target),
+            Some(content_hash(&target).unwrap().as_str())
+        );
+    }
+
+    #[test]
+    fn updates_copy_when_repository_file_changes() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        let manifest = copy_manifest(&target);
+        let ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        fs::write(repo.path().join("myfile"), "changed").unwrap();
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.updated.len(), 1);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "changed");
+        let again = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn edited_copy_is_a_conflict() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        let manifest = copy_manifest(&target);
+        let ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        fs::write(&target, "edited by the app").unwrap();
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "edited by the app");
+
+        let adopt = SyncOptions {
+            on_conflict: ConflictStrategy::Adopt,
+            ..SyncOptions::default()
+        };
+        SyncCommand::sync_manifest(&manifest, &adopt, &ctx).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.path().join("myfile")).unwrap(),
+            "edited by the app"
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "edited by the app");
+        let again = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn replaces_old_symlink_with_copy() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        create_symlink(repo.path().join("myfile"), &target).unwrap();
 
-        assert!(result.created.is_empty());
-        assert!(result.conflicts.is_empty());
+        let result = SyncCommand::sync_manifest(
+            &copy_manifest(&target),
+            &SyncOptions::default(),
+            &Context::new(repo.path()),
+        )
+        .unwrap();
+
+        assert_eq!(result.updated.len(), 1);
+        assert!(target.symlink_metadata().unwrap().is_file());
+    }
+
+    #[test]
+    fn creates_hard_link() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("myfile");
+        let manifest = Manifest::parse(&format!(
+            "version = 2\n[files.myfile]\ntarget = {target:?}\nmode = \"hardlink\"\n"
+        ))
+        .unwrap();
+        let ctx = Context::new(repo.path());
+
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.created.len(), 1);
+        assert!(result.fallbacks.is_empty());
+        assert!(same_inode(&repo.path().join("myfile"), &target).unwrap());
+
+        // A broken link with unchanged contents is restored
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, "content").unwrap();
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.updated.len(), 1);
+        assert!(same_inode(&repo.path().join("myfile"), &target).unwrap());
+    }
+
+    #[test]
+    fn hard_link_falls_back_to_copy_across_filesystems() {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only meaningful where a tmpfs is mounted separately from the temp dir
+        let Ok(other) = TempDir::new_in("/dev/shm") else {
+            return;
+        };
+        let repo = repo_with_file();
+        if repo.path().metadata().unwrap().dev() == other.path().metadata().unwrap().dev() {
+            return;
+        }
+
+        let target = other.path().join("myfile");
+        let manifest = Manifest::parse(&format!(
+            "version = 2\n[files.myfile]\ntarget = {target:?}\nmode = \"hardlink\"\n"
+        ))
+        .unwrap();
+        let ctx = Context::new(repo.path());
+
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.fallbacks, [target.as_path()]);
+        assert_eq!(result.created[0].mode, LinkMode::Copy);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        let again = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn creates_relative_symlinks() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join("nested/myfile");
+        let manifest = Manifest::parse(&format!(
+            "version = 2\n[defaults]\nrelative = true\n[files]\nmyfile = {target:?}\n"
+        ))
+        .unwrap();
+        let ctx = Context::new(repo.path());
+
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        let destination = fs::read_link(&target).unwrap();
+        assert!(destination.is_relative());
+        assert!(destination.starts_with(".."));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+        let again = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert!(again.is_empty());
+    }
+
+    #[test]
+    fn skips_entries_whose_condition_fails() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut ctx = Context::new(repo.path());
+        ctx.facts = Rc::new(FakeFacts {
+            os: "linux".into(),
+            commands: vec!["nvim".into()],
+            ..FakeFacts::default()
+        });
+        let mut manifest = Manifest::empty();
+        let nvim = Entry {
+            condition: Condition {
+                command: Some("nvim".into()),
+                ..Condition::default()
+            },
+            ..Entry::new(target_dir.path().join("init.lua"))
+        };
+        // Missing from the repository, which would fail if it were deployed
+        let emacs = Entry {
+            condition: Condition {
+                command: Some("emacs".into()),
+                ..Condition::default()
+            },
+            ..Entry::new(target_dir.path().join("init.el"))
+        };
+        fs::write(repo.path().join("init.lua"), "").unwrap();
+        manifest.insert_entry("init.lua".into(), nvim);
+        manifest.insert_entry("init.el".into(), emacs);
+
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.skipped, [PathBuf::from("init.el")]);
+        assert_eq!(result.created.len(), 1);
+        assert!(!target_dir.path().join("init.el").exists());
+    }
+
+    #[test]
+    fn renders_templates_and_detects_hand_edits() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join(".gitconfig");
+        fs::write(repo.path().join("gitconfig"), "email = {{ email }}\n").unwrap();
+        let mut manifest =
+            Manifest::parse("version = 2\n\n[vars]\nemail = \"me@work.example\"\n\n[files]\n")
+                .unwrap();
+        let template = Entry {
+            mode: LinkMode::Template,
+            ..Entry::new(target.clone())
+        };
+        manifest.insert_entry("gitconfig".into(), template);
+        let ctx = Context::new(repo.path());
+        let link = &ctx.links(&manifest).unwrap()[0];
+        let inspect = || {
+            let state = State::load_from(&ctx.state_path()).unwrap();
+            ctx.inspect(link, &state, &ctx.vars(&manifest)).unwrap()
+        };
+
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.created[0].mode, LinkMode::Template);
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "email = me@work.example\n"
+        );
+        assert_eq!(inspect(), LinkState::Linked);
+
+        // Changing the template only needs a re-render
+        fs::write(
+            repo.path().join("gitconfig"),
+            "[user]\nemail = {{ email }}\n",
+        )
+        .unwrap();
+        assert_eq!(inspect(), LinkState::Outdated);
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.updated.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "[user]\nemail = me@work.example\n"
+        );
+
+        fs::write(&target, "[user]\nemail = edited@example.com\n").unwrap();
+        assert_eq!(inspect(), LinkState::Modified);
+        let adopt = SyncOptions {
+            on_conflict: ConflictStrategy::Adopt,
+            ..SyncOptions::default()
+        };
+        let result = SyncCommand::sync_manifest(&manifest, &adopt, &ctx);
+        assert!(matches!(result, Err(Error::AdoptTemplate(_))));
+    }
+
+    #[test]
+    fn decrypts_secrets_privately_and_adopts_edits() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let target = target_dir.path().join(".netrc");
+        let ctx = Context::new(repo.path());
+        ctx.key
+            .set(Rc::new(Key::Passphrase("pass".to_string().into())))
+            .unwrap();
+        let local = repo.path().join("netrc");
+        fs::write(
+            &local,
+            ctx.key().unwrap().encrypt(b"password hunter2").unwrap(),
+        )
+        .unwrap();
+        let mut manifest = Manifest::empty();
+        let secret = Entry {
+            mode: LinkMode::Encrypted,
+            ..Entry::new(target.clone())
+        };
+        manifest.insert_entry("netrc".into(), secret);
+        let link = &ctx.links(&manifest).unwrap()[0];
+        let inspect = || {
+            let state = State::load_from(&ctx.state_path()).unwrap();
+            ctx.inspect(link, &state, &ctx.vars(&manifest)).unwrap()
+        };
+
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.created[0].mode, LinkMode::Encrypted);
+        assert_eq!(fs::read(&target).unwrap(), b"password hunter2");
+        let mode = target.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(inspect(), LinkState::Linked);
+
+        fs::write(&target, "password changed").unwrap();
+        assert_eq!(inspect(), LinkState::Modified);
+        let adopt = SyncOptions {
+            on_conflict: ConflictStrategy::Adopt,
+            ..SyncOptions::default()
+        };
+        SyncCommand::sync_manifest(&manifest, &adopt, &ctx).unwrap();
+
+        assert!(!fs::read_to_string(&local).unwrap().contains("password"));
+        assert_eq!(
+            ctx.key().unwrap().decrypt_file(&local).unwrap(),
+            b"password changed"
+        );
+        assert_eq!(inspect(), LinkState::Linked);
+    }
+
+    #[test]
+    fn applies_permissions_to_repo_files_copies_and_new_directories() {
+        let repo = repo_with_file();
+        let target_dir = TempDir::new().unwrap();
+        let local = repo.path().join("myfile");
+        fs::set_permissions(&local, fs::Permissions::from_mode(0o644)).unwrap();
+        let linked = target_dir.path().join(".ssh/config");
+        let copied = target_dir.path().join("copied");
+        let mut manifest = Manifest::empty();
+        let private = |target: &Path, mode| Entry {
+            mode,
+            permissions: Some(0o600),
+            ..Entry::new(target.to_path_buf())
+        };
+        manifest.insert_entry("myfile".into(), private(&linked, LinkMode::Symlink));
+        fs::copy(&local, repo.path().join("copy")).unwrap();
+        manifest.insert_entry("copy".into(), private(&copied, LinkMode::Copy));
+        let ctx = Context::new(repo.path());
+        let permissions = |path: &Path| path.metadata().unwrap().permissions().mode() & 0o777;
+
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(permissions(&local), 0o600);
+        assert_eq!(permissions(&copied), 0o600);
+        assert_eq!(permissions(&target_dir.path().join(".ssh")), 0o700);
+
+        // Drift on the copy is reported and fixed
+        fs::set_permissions(&copied, fs::Permissions::from_mode(0o640)).unwrap();
+        let state = State::load_from(&ctx.state_path()).unwrap();
+        let link = &ctx.links(&manifest).unwrap()[0];
+        assert_eq!(link.target, copied);
+        assert_eq!(
+            ctx.inspect(link, &state, &ctx.vars(&manifest)).unwrap(),
+            LinkState::WrongPermissions(0o640)
+        );
+        let result = SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(result.permissions, [(copied.clone(), 0o600)]);
+        assert_eq!(permissions(&copied), 0o600);
+    }
+
+    #[test]
+    fn applies_permissions_to_every_file_of_unfolded_copies() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join("gnupg/private")).unwrap();
+        fs::write(repo.path().join("gnupg/gpg.conf"), "").unwrap();
+        fs::write(repo.path().join("gnupg/private/key"), "").unwrap();
+        let gnupg = target_dir.path().join(".gnupg");
+        let mut manifest = Manifest::empty();
+        manifest.insert_entry(
+            "gnupg".into(),
+            Entry {
+                mode: LinkMode::Copy,
+                dir: Some(DirMode::Unfold),
+                permissions: Some(0o600),
+                ..Entry::new(gnupg.clone())
+            },
+        );
+        let ctx = Context::new(repo.path());
+        let permissions = |path: &Path| path.metadata().unwrap().permissions().mode() & 0o777;
+
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(permissions(&gnupg.join("gpg.conf")), 0o600);
+        assert_eq!(permissions(&gnupg.join("private/key")), 0o600);
+        assert_eq!(permissions(&gnupg), 0o700);
+        assert_eq!(permissions(&gnupg.join("private")), 0o700);
+
+        fs::set_permissions(gnupg.join("private/key"), fs::Permissions::from_mode(0o644)).unwrap();
+        let statuses = StatusCommand::status_manifest(&manifest, &ctx).unwrap();
+        let states: Vec<_> = statuses.into_iter().map(|s| s.state).collect();
+        assert_eq!(
+            states,
+            [LinkState::Linked, LinkState::WrongPermissions(0o644)]
+        );
+
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        assert_eq!(permissions(&gnupg.join("private/key")), 0o600);
+    }
+
+    #[test]
+    fn prunes_links_of_removed_entries() {
+        let repo = repo_with_file();
+        fs::write(repo.path().join("other"), "").unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let ours = target_dir.path().join("myfile");
+        let repointed = target_dir.path().join("other");
+        let mut manifest = Manifest::empty();
+        manifest.insert("myfile".into(), &ours).unwrap();
+        manifest.insert("other".into(), &repointed).unwrap();
+        let ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        // A link the user has pointed elsewhere is no longer ours to remove
+        fs::remove_file(&repointed).unwrap();
+        create_symlink(target_dir.path(), &repointed).unwrap();
+        let result =
+            SyncCommand::sync_manifest(&Manifest::empty(), &SyncOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.pruned, std::slice::from_ref(&ours));
+        assert!(ours.symlink_metadata().is_err());
+        assert!(repointed.symlink_metadata().is_ok());
+        assert!(
+            State::load_from(&ctx.state_path())
+                .unwrap()
+                .links()
+                .is_empty()
+        );
     }
 }
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+
+use crate::commands::Command;
+use crate::context::Context;
+use crate::entry::LinkMode;
+use crate::error::Result;
+use crate::link::LinkState;
+use crate::manifest::Manifest;
+use crate::plan::{Action, Plan};
+use crate::state::State;
+
+pub struct UnlinkCommand {
+    options: UnlinkOptions,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct UnlinkOptions {
+    /// Also remove copied, rendered and decrypted targets, which are the
+    /// only copy of their content outside the repository
+    pub all: bool,
+}
+
+impl UnlinkCommand {
+    pub fn new(options: UnlinkOptions) -> Self {
+        Self { options }
+    }
+
+    /// Work out which deployed targets to remove. Only targets that are
+    /// still exactly what `dot` deployed are removed; anything else is
+    /// reported and left alone. Without `all`, only symlinks and hard
+    /// links are removed.
+    pub fn plan_unlink(
+        manifest: &Manifest,
+        state: &mut State,
+        options: &UnlinkOptions,
+        ctx: &Context,
+    ) -> Result<(UnlinkResult, Plan)> {
+        let mut result = UnlinkResult::default();
+        let mut plan = Plan::new();
+        let vars = ctx.vars(manifest);
+
+        for link in ctx.links(manifest)? {
+            let materialized = !matches!(
+                ctx.deploy_mode(&link)?,
+                LinkMode::Symlink | LinkMode::Hardlink
+            );
+            match ctx.inspect(&link, state, &vars)? {
+                LinkState::Missing | LinkState::Skipped => {}
+                _ if materialized && !options.all => result.materialized.push(link.target),
+                LinkState::Linked | LinkState::WrongPermissions(_) => {
+                    plan.push(Action::Remove(link.target.clone()));
+                    state.forget(&link.target);
+                    result.removed.push(link.target);
+                }
+                other => result.kept.push((link.target, other)),
+            }
+        }
+
+        Ok((result, plan))
+    }
+
+    /// Core logic separated for testing.
+    ///
+    /// The repository and manifest are never touched, so `dot sync`
+    /// deploys everything again.
+    pub fn unlink_manifest(
+        manifest: &Manifest,
+        options: &UnlinkOptions,
+        ctx: &Context,
+    ) -> Result<UnlinkResult> {
+        let loaded = State::load_from(&ctx.state_path())?;
+        let mut state = loaded.clone();
+        let (result, plan) = Self::plan_unlink(manifest, &mut state, options, ctx)?;
+
+        let transaction = ctx.begin(&plan)?;
+        if !ctx.dry_run
+            && state != loaded
+            && let Err(e) = state.save_to(&ctx.state_path())
+        {
+            return Err(transaction.rollback_after(e));
+        }
+        transaction.commit()?;
+
+        Ok(result)
+    }
+}
+
+impl Default for UnlinkCommand {
+    fn default() -> Self {
+        Self::new(UnlinkOptions::default())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct UnlinkResult {
+    /// Targets that were removed
+    pub removed: Vec<PathBuf>,
+    /// Targets left in place because they are not what `dot` deployed
+    pub kept: Vec<(PathBuf, LinkState)>,
+    /// Copied, rendered or decrypted targets left in place without `all`
+    pub materialized: Vec<PathBuf>,
+}
+
+impl Command for UnlinkCommand {
+    fn execute(self, ctx: &Context) -> Result<()> {
+        let manifest = Manifest::load_from(&ctx.manifest_path())?;
+        let result = Self::unlink_manifest(&manifest, &self.options, ctx)?;
+
+        for (target, state) in &result.kept {
+            eprintln!("Warning: left {} in place ({state})", target.display());
+        }
+        for target in &result.materialized {
+            println!(
+                "Left {} in place (use --all to remove it)",
+                target.display()
+            );
+        }
+
+        if result.removed.is_empty() {
+            println!("Nothing to unlink");
+        }
+
+        if ctx.dry_run {
+            return Ok(());
+        }
+
+        for removed in &result.removed {
+            println!("Removed {}", removed.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{SyncCommand, SyncOptions};
+    use crate::entry::{Entry, LinkMode};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn removes_deployed_links_and_keeps_the_repository() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        fs::write(repo.path().join("vimrc"), "set nu").unwrap();
+        fs::write(repo.path().join("gitconfig"), "[user]").unwrap();
+        let linked = target_dir.path().join(".vimrc");
+        let copied = target_dir.path().join(".gitconfig");
+        let mut manifest = Manifest::empty();
+        manifest.insert("vimrc".into(), &linked).unwrap();
+        manifest.insert_entry(
+            "gitconfig".into(),
+            Entry {
+                mode: LinkMode::Copy,
+                ..Entry::new(copied.clone())
+            },
+        );
+        let ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        let result =
+            UnlinkCommand::unlink_manifest(&manifest, &UnlinkOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.removed, std::slice::from_ref(&linked));
+        assert_eq!(result.materialized, std::slice::from_ref(&copied));
+        assert!(linked.symlink_metadata().is_err());
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "[user]");
+        assert_ne!(
+            State::load_from(&ctx.state_path()).unwrap(),
+            State::default()
+        );
+
+        let all = UnlinkOptions { all: true };
+        let result = UnlinkCommand::unlink_manifest(&manifest, &all, &ctx).unwrap();
+
+        assert_eq!(result.removed, std::slice::from_ref(&copied));
+        assert!(copied.symlink_metadata().is_err());
+        assert_eq!(
+            fs::read_to_string(repo.path().join("vimrc")).unwrap(),
+            "set nu"
+        );
+        assert_eq!(
+            State::load_from(&ctx.state_path()).unwrap(),
+            State::default()
+        );
+    }
+
+    #[test]
+    fn keeps_edited_copies() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        fs::write(repo.path().join("gitconfig"), "[user]").unwrap();
+        let copied = target_dir.path().join(".gitconfig");
+        let mut manifest = Manifest::empty();
+        manifest.insert_entry(
+            "gitconfig".into(),
+            Entry {
+                mode: LinkMode::Copy,
+                ..Entry::new(copied.clone())
+            },
+        );
+        let ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+        fs::write(&copied, "[user] edited").unwrap();
+
+        let all = UnlinkOptions { all: true };
+        let result = UnlinkCommand::unlink_manifest(&manifest, &all, &ctx).unwrap();
+
+        assert!(result.removed.is_empty());
+        assert_eq!(result.kept, [(copied.clone(), LinkState::Modified)]);
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "[user] edited");
+    }
+
+    #[test]
+    fn dry_run_removes_nothing() {
+        let repo = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        fs::write(repo.path().join("vimrc"), "").unwrap();
+        let linked = target_dir.path().join(".vimrc");
+        let mut manifest = Manifest::empty();
+        manifest.insert("vimrc".into(), &linked).unwrap();
+        let mut ctx = Context::new(repo.path());
+        SyncCommand::sync_manifest(&manifest, &SyncOptions::default(), &ctx).unwrap();
+
+        ctx.dry_run = true;
+        let result =
+            UnlinkCommand::unlink_manifest(&manifest, &UnlinkOptions::default(), &ctx).unwrap();
+
+        assert_eq!(result.removed, std::slice::from_ref(&linked));
+        assert!(linked.symlink_metadata().is_ok());
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::entry::compile;
+use crate::error::Result;
+use crate::facts::Facts;
+
+/// Requirements on the machine an entry deploys to. Every field that is
+/// set must hold; an empty condition always holds.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    /// Operating system, e.g. `linux` or `macos`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    /// Hostname glob
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Environment variable that must be set and non-empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// Path that must exist, with `~` expanded like targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exists: Option<PathBuf>,
+    /// Executable that must be on `PATH`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+impl Condition {
+    /// Check that the hostname pattern is a valid glob.
+    pub fn validate(&self) -> Result<()> {
+        compile(self.hostname.as_slice())?;
+        Ok(())
+    }
+
+    /// Evaluate against `facts`, resolving `exists` paths with `resolve`.
+    pub fn holds(
+        &self,
+        facts: &dyn Facts,
+        resolve: impl Fn(&Path) -> Result<PathBuf>,
+    ) -> Result<bool> {
+        if let Some(os) = &self.os
+            && os != facts.os()
+        {
+            return Ok(false);
+        }
+        if let Some(pattern) = &self.hostname {
+            let matches =
+                compile(std::slice::from_ref(pattern))?[0].matches(facts.hostname().unwrap_or(""));
+            if !matches {
+                return Ok(false);
+            }
+        }
+        if let Some(name) = &self.env
+            && facts.env(name).is_none_or(|value| value.is_empty())
+        {
+            return Ok(false);
+        }
+        if let Some(path) = &self.exists
+            && !resolve(path)?.exists()
+        {
+            return Ok(false);
+        }
+        if let Some(command) = &self.command
+            && !facts.has_command(command)
+        {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::facts::FakeFacts;
+
+    fn linux_work() -> FakeFacts {
+        FakeFacts {
+            os: "linux".into(),
+            hostname: Some("work-42".into()),
+            env: [("WAYLAND_DISPLAY".into(), "wayland-0".into())].into(),
+            commands: vec!["nvim".into()],
+        }
+    }
+
+    fn holds(condition: Condition) -> bool {
+        condition
+            .holds(&linux_work(), |p| Ok(p.to_path_buf()))
+            .unwrap()
+    }
+
+    #[test]
+    fn empty_condition_holds() {
+        assert!(holds(Condition::default()));
+    }
+
+    #[test]
+    fn every_field_must_hold() {
+        let all = Condition {
+            os: Some("linux".into()),
+            hostname: Some("work-*".into()),
+            env: Some("WAYLAND_DISPLAY".into()),
+            exists: Some("/".into()),
+            command: Some("nvim".into()),
+        };
+        assert!(holds(all.clone()));
+
+        assert!(!holds(Condition {
+            os: Some("macos".into()),
+            ..all.clone()
+        }));
+        assert!(!holds(Condition {
+            hostname: Some("home-*".into()),
+            ..all.clone()
+        }));
+        assert!(!holds(Condition {
+            env: Some("DISPLAY".into()),
+            ..all.clone()
+        }));
+        assert!(!holds(Condition {
+            exists: Some("/nonexistent/kitty".into()),
+            ..all.clone()
+        }));
+        assert!(!holds(Condition {
+            command: Some("emacs".into()),
+            ..all
+        }));
+    }
+}
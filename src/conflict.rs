@@ -0,0 +1,201 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use crate::error::Result;
+
+/// How to handle a regular file or directory that occupies a link target.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConflictStrategy {
+    /// Leave the existing file alone and report it
+    #[default]
+    Skip,
+    /// Move the existing file to a timestamped backup, then link
+    Backup,
+    /// Delete the existing file, then link
+    Overwrite,
+    /// Replace the repository copy with the existing file, then link
+    Adopt,
+    /// Ask which strategy to use for each conflict
+    Ask,
+}
+
+impl ConflictStrategy {
+    /// Turn `Ask` into a concrete strategy by prompting on the terminal.
+    pub fn resolve(self, target: &Path) -> Result<Self> {
+        match self {
+            Self::Ask => ask(target, &mut io::stdin().lock(), &mut io::stderr()),
+            strategy => Ok(strategy),
+        }
+    }
+}
+
+/// Prompt until a valid answer is given. End of input means skip.
+pub fn ask(
+    target: &Path,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<ConflictStrategy> {
+    loop {
+        write!(
+            output,
+            "{} already exists. [b]ackup, [o]verwrite, [a]dopt or [s]kip? ",
+            target.display()
+        )?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(ConflictStrategy::Skip);
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "b" | "backup" => return Ok(ConflictStrategy::Backup),
+            "o" | "overwrite" => return Ok(ConflictStrategy::Overwrite),
+            "a" | "adopt" => return Ok(ConflictStrategy::Adopt),
+            "s" | "skip" => return Ok(ConflictStrategy::Skip),
+            _ => {}
+        }
+    }
+}
+
+/// Pick an unused `<target>.<timestamp>.bak` path next to the target.
+pub fn backup_path(target: &Path) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let stamp = format_timestamp(secs);
+
+    let mut counter = 0;
+    loop {
+        let mut name = target.file_name().map(OsString::from).unwrap_or_default();
+        name.push(format!(".{stamp}"));
+        if counter > 0 {
+            name.push(format!(".{counter}"));
+        }
+        name.push(".bak");
+
+        let candidate = target.with_file_name(name);
+        if candidate.symlink_metadata().is_err() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Remove a file, symlink or whole directory tree.
+pub fn remove_path(path: &Path) -> Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Format seconds since the epoch as a compact UTC `YYYYMMDDTHHMMSS` stamp.
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days conversion for the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[test]
+    fn formats_epoch() {
+        assert_eq!(format_timestamp(0), "19700101T000000");
+    }
+
+    #[test]
+    fn formats_leap_day() {
+        assert_eq!(format_timestamp(1_709_210_096), "20240229T123456");
+    }
+
+    #[test]
+    fn backup_path_avoids_existing_files() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join(".bashrc");
+
+        let first = backup_path(&target);
+        fs::write(&first, "").unwrap();
+        let second = backup_path(&target);
+
+        assert_ne!(first, second);
+        assert!(
+            first
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with(".bashrc.")
+        );
+        assert!(second.to_str().unwrap().ends_with(".1.bak"));
+    }
+
+    #[test]
+    fn ask_accepts_short_answers() {
+        let mut output = Vec::new();
+        let answer = ask(Path::new("f"), &mut Cursor::new("a\n"), &mut output).unwrap();
+        assert_eq!(answer, ConflictStrategy::Adopt);
+    }
+
+    #[test]
+    fn ask_reprompts_on_invalid_answer() {
+        let mut output = Vec::new();
+        let answer = ask(Path::new("f"), &mut Cursor::new("x\nbackup\n"), &mut output).unwrap();
+        assert_eq!(answer, ConflictStrategy::Backup);
+        assert_eq!(
+            String::from_utf8(output)
+                .unwrap()
+                .matches("[s]kip?")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn ask_skips_on_end_of_input() {
+        let mut output = Vec::new();
+        let answer = ask(Path::new("f"), &mut Cursor::new(""), &mut output).unwrap();
+        assert_eq!(answer, ConflictStrategy::Skip);
+    }
+
+    #[test]
+    fn remove_path_handles_directories() {
+        let dir = TempDir::new().unwrap();
+        let nested = dir.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("c"), "").unwrap();
+
+        remove_path(&dir.path().join("a")).unwrap();
+
+        assert!(!dir.path().join("a").exists());
+    }
+}
@@ -0,0 +1,561 @@
+use std::cell::OnceCell;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::entry::{Entry, LinkMode};
+use crate::error::{Error, Result};
+use crate::facts::{Facts, SystemFacts};
+use crate::link::{LinkState, same_device};
+use crate::manifest::{MANIFEST_FILE, Manifest};
+use crate::path::{collapse_tilde_with_home, expand_tilde_with_home, to_lexical_absolute};
+use crate::plan::{Plan, Transaction};
+use crate::secret::Key;
+use crate::state::{STATE_FILE, State};
+use crate::template::Vars;
+
+/// Options shared by every command, set through global CLI flags.
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// Root of the dot repository, where `dot.toml` lives
+    pub repo: PathBuf,
+    /// Directory that `~` in the manifest expands to
+    pub home: Option<PathBuf>,
+    /// Prefix prepended to every deployed path, like `DESTDIR`
+    pub target_root: Option<PathBuf>,
+    /// Print planned changes instead of applying them
+    pub dry_run: bool,
+    /// Profiles chosen with `--profile`, overriding hostname selection
+    pub profiles: Vec<String>,
+    /// The machine that profiles and conditions are evaluated against
+    pub facts: Rc<dyn Facts>,
+    /// Age identity file for encrypted entries, instead of a passphrase
+    pub key_file: Option<PathBuf>,
+    /// Key for encrypted entries, loaded on first use
+    pub key: OnceCell<Rc<Key>>,
+}
+
+impl Context {
+    pub fn new(repo: impl Into<PathBuf>) -> Self {
+        Self {
+            repo: repo.into(),
+            home: dirs::home_dir(),
+            target_root: None,
+            dry_run: false,
+            profiles: Vec::new(),
+            facts: Rc::new(SystemFacts::new()),
+            key_file: None,
+            key: OnceCell::new(),
+        }
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.repo.join(MANIFEST_FILE)
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        self.repo.join(STATE_FILE)
+    }
+
+    /// Resolve a manifest key to its location in the repository.
+    pub fn local(&self, file: &Path) -> PathBuf {
+        self.repo.join(file)
+    }
+
+    /// Iterate over manifest entries with their deployed paths.
+    pub fn targets<'a>(
+        &self,
+        manifest: &'a Manifest,
+    ) -> impl Iterator<Item = (&'a Path, Result<PathBuf>)> + 'a {
+        let target_root = self.target_root.clone();
+        manifest
+            .iter_with_home(self.home.clone())
+            .map(move |(local, target)| (local, target.map(|t| rooted(target_root.as_deref(), t))))
+    }
+
+    /// Every path the manifest deploys on this machine.
+    ///
+    /// Entries outside the active profiles are left out, and entries whose
+    /// condition fails are a single link marked `skipped`. Unfolded
+    /// directories expand to one link per selected file; folded directories
+    /// and plain files are a single link.
+    pub fn links(&self, manifest: &Manifest) -> Result<Vec<Link>> {
+        let mut links = Vec::new();
+        for (file, entry, target) in self.entries(manifest)? {
+            let local = self.local(file);
+            let skipped = !self.condition_holds(entry)?;
+
+            if entry.is_unfolded() && local.is_dir() && !skipped {
+                for relative in entry.selected_files(&local)? {
+                    links.push(Link {
+                        file: file.join(&relative),
+                        target: target.join(&relative),
+                        mode: entry.mode,
+                        relative: manifest.is_relative(entry),
+                        skipped,
+                        // An unfolded entry's permissions apply to each file
+                        permissions: entry.deployed_permissions(),
+                    });
+                }
+            } else {
+                links.push(Link {
+                    file: file.to_path_buf(),
+                    target,
+                    mode: entry.mode,
+                    relative: manifest.is_relative(entry),
+                    skipped,
+                    permissions: entry.deployed_permissions(),
+                });
+            }
+        }
+        Ok(links)
+    }
+
+    /// Manifest entries in the active profiles, with their deployed paths.
+    pub fn entries<'a>(
+        &self,
+        manifest: &'a Manifest,
+    ) -> Result<Vec<(&'a Path, &'a Entry, PathBuf)>> {
+        let profiles = manifest.active_profiles(&self.profiles, self.facts.hostname())?;
+        let mut entries = Vec::new();
+        for (file, target) in self.targets(manifest) {
+            let Some(entry) = manifest.entry(file) else {
+                continue;
+            };
+            if !profiles.is_empty() && !profiles.iter().any(|p| p.includes(file, entry)) {
+                continue;
+            }
+            entries.push((file, entry, target?));
+        }
+        Ok(entries)
+    }
+
+    /// Whether `entry` should be deployed on this machine.
+    pub fn condition_holds(&self, entry: &Entry) -> Result<bool> {
+        entry.condition.holds(&*self.facts, |p| self.expand(p))
+    }
+
+    /// The key for encrypted entries, prompting for a passphrase at most
+    /// once per command.
+    pub fn key(&self) -> Result<Rc<Key>> {
+        if let Some(key) = self.key.get() {
+            return Ok(key.clone());
+        }
+        let key = Rc::new(Key::load(self.key_file.as_deref())?);
+        Ok(self.key.get_or_init(|| key).clone())
+    }
+
+    /// Variables that templates are rendered with.
+    pub fn vars<'a>(&'a self, manifest: &Manifest) -> Vars<'a> {
+        Vars::new(&*self.facts, manifest.vars())
+    }
+
+    /// Classify the deployed state of a link according to its mode, and
+    /// then its permissions.
+    pub fn inspect(&self, link: &Link, state: &State, vars: &Vars) -> Result<LinkState> {
+        if link.skipped {
+            return Ok(LinkState::Skipped);
+        }
+        let inspected = self.inspect_content(link, state, vars)?;
+        if inspected != LinkState::Linked {
+            return Ok(inspected);
+        }
+        match link.permissions {
+            Some(expected) => {
+                let path = self.permissions_path(link)?;
+                let actual = path.metadata()?.permissions().mode() & 0o7777;
+                if actual == expected {
+                    Ok(LinkState::Linked)
+                } else {
+                    Ok(LinkState::WrongPermissions(actual))
+                }
+            }
+            None => Ok(LinkState::Linked),
+        }
+    }
+
+    /// The file whose permissions a deployed link is seen with: the
+    /// repository file behind symlinks and hard links, otherwise the target.
+    pub fn permissions_path(&self, link: &Link) -> Result<PathBuf> {
+        match self.deploy_mode(link)? {
+            LinkMode::Symlink | LinkMode::Hardlink => Ok(self.local(&link.file)),
+            LinkMode::Copy | LinkMode::Template | LinkMode::Encrypted => Ok(link.target.clone()),
+        }
+    }
+
+    fn inspect_content(&self, link: &Link, state: &State, vars: &Vars) -> Result<LinkState> {
+        let local = self.local(&link.file);
+        match self.deploy_mode(link)? {
+            LinkMode::Symlink => LinkState::inspect(&local, &link.target, &self.repo),
+            LinkMode::Copy => {
+                LinkState::inspect_copy(&local, &link.target, state.copy_hash(&link.target))
+            }
+            LinkMode::Hardlink => LinkState::inspect_hardlink(&local, &link.target),
+            LinkMode::Template => LinkState::inspect_template(
+                &local,
+                &link.target,
+                vars,
+                state.copy_hash(&link.target),
+            ),
+            LinkMode::Encrypted => LinkState::inspect_encrypted(
+                &local,
+                &link.target,
+                state.copy_hash(&link.target),
+                state.source_hash(&link.target),
+            ),
+        }
+    }
+
+    /// The mode a link is actually deployed with: hard links fall back to
+    /// copies when the target is on another filesystem than the repository.
+    pub fn deploy_mode(&self, link: &Link) -> Result<LinkMode> {
+        let local = self.local(&link.file);
+        match link.mode {
+            LinkMode::Hardlink if local.exists() && !same_device(&local, &link.target)? => {
+                Ok(LinkMode::Copy)
+            }
+            mode => Ok(mode),
+        }
+    }
+
+    /// Deployed path of a single manifest entry.
+    pub fn target(&self, manifest: &Manifest, file: &Path) -> Option<PathBuf> {
+        manifest
+            .get_with_home(file, self.home.clone())
+            .map(|t| rooted(self.target_root.as_deref(), t))
+    }
+
+    /// The manifest key of the entry `arg` names, either by its repository
+    /// path or by where it is deployed (`~/.vimrc`, `/home/me/.vimrc`).
+    pub fn resolve(&self, manifest: &Manifest, arg: &Path) -> Result<PathBuf> {
+        let mut matches: Vec<PathBuf> = Vec::new();
+        let mut add = |key: &Path| {
+            if !matches.iter().any(|m| m == key) {
+                matches.push(key.to_path_buf());
+            }
+        };
+
+        if manifest.entry(arg).is_some() {
+            add(arg);
+        }
+        let absolute = if arg.starts_with("~") {
+            self.expand(arg)?
+        } else {
+            to_lexical_absolute(arg)?
+        };
+        if let Ok(key) = absolute.strip_prefix(&self.repo)
+            && manifest.entry(key).is_some()
+        {
+            add(key);
+        }
+        for (key, target) in self.targets(manifest) {
+            if target? == absolute {
+                add(key);
+            }
+        }
+
+        match matches.as_slice() {
+            [] => Err(Error::NotFound(arg.to_path_buf())),
+            [key] => Ok(key.clone()),
+            [first, second, ..] => Err(Error::AmbiguousEntry(
+                arg.to_path_buf(),
+                first.clone(),
+                second.clone(),
+            )),
+        }
+    }
+
+    /// Where a manifest-style path such as `~/.config/kitty` is on disk.
+    pub fn expand(&self, path: &Path) -> Result<PathBuf> {
+        let expanded = expand_tilde_with_home(path, self.home.clone())?;
+        Ok(rooted(self.target_root.as_deref(), expanded))
+    }
+
+    /// The form of `target` stored in the manifest, e.g. `~/.vimrc`.
+    pub fn collapse(&self, target: &Path) -> Result<PathBuf> {
+        collapse_tilde_with_home(&self.unrooted(target)?, self.home.clone())
+    }
+
+    /// Absolute `target` with the `--target-root` prefix removed.
+    fn unrooted(&self, target: &Path) -> Result<PathBuf> {
+        let target = to_lexical_absolute(target)?;
+        match &self.target_root {
+            Some(root) if target.starts_with(root) => {
+                let suffix = target.strip_prefix(root).expect("checked above");
+                Ok(Path::new("/").join(suffix))
+            }
+            _ => Ok(target),
+        }
+    }
+
+    /// Execute a plan but keep it revertible until the returned transaction
+    /// is committed. With `--dry-run` the plan is printed and the transaction
+    /// is empty.
+    pub fn begin(&self, plan: &Plan) -> Result<Transaction> {
+        self.begin_with(plan, |_| Ok(()))
+    }
+
+    /// Like `begin`, running `check` before each action as
+    /// `Plan::begin_with` does.
+    pub fn begin_with(
+        &self,
+        plan: &Plan,
+        check: impl FnMut(usize) -> Result<()>,
+    ) -> Result<Transaction> {
+        if self.dry_run {
+            for action in plan.actions() {
+                println!("Would {action}");
+            }
+            return Ok(Transaction::default());
+        }
+
+        plan.begin_with(check)
+    }
+}
+
+/// A single deployed path: a tracked file, a folded directory, or one file
+/// of an unfolded directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// Path inside the repository, relative to its root
+    pub file: PathBuf,
+    /// Where the file is deployed
+    pub target: PathBuf,
+    pub mode: LinkMode,
+    /// Whether a symlink uses a path relative to the target's directory
+    pub relative: bool,
+    /// The entry's condition fails on this machine, so it is not deployed
+    pub skipped: bool,
+    /// Permission bits the deployed file must have
+    pub permissions: Option<u32>,
+}
+
+/// Re-anchor an absolute path under `root`, if one is set.
+fn rooted(root: Option<&Path>, path: PathBuf) -> PathBuf {
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(&path)),
+        None => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::facts::FakeFacts;
+    use std::fs;
+
+    fn on_host(hostname: Option<&str>) -> Rc<FakeFacts> {
+        Rc::new(FakeFacts {
+            os: "linux".into(),
+            hostname: hostname.map(String::from),
+            ..FakeFacts::default()
+        })
+    }
+
+    fn context() -> Context {
+        let mut ctx = Context::new("/repo");
+        ctx.home = Some("/home/user".into());
+        ctx.facts = on_host(Some("host"));
+        ctx
+    }
+
+    #[test]
+    fn targets_expand_with_home_override() {
+        let mut ctx = context();
+        ctx.home = Some("/scratch/home".into());
+        let manifest = Manifest::parse(r#""vimrc" = "~/.vimrc""#).unwrap();
+
+        let targets: Vec<_> = ctx.targets(&manifest).map(|(_, t)| t.unwrap()).collect();
+
+        assert_eq!(targets, [PathBuf::from("/scratch/home/.vimrc")]);
+    }
+
+    #[test]
+    fn targets_are_prefixed_with_target_root() {
+        let mut ctx = context();
+        ctx.target_root = Some("/stage".into());
+        let manifest = Manifest::parse(
+            r#"
+            "vimrc" = "~/.vimrc"
+            "hosts" = "/etc/hosts"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            ctx.target(&manifest, Path::new("vimrc")),
+            Some(PathBuf::from("/stage/home/user/.vimrc"))
+        );
+        assert_eq!(
+            ctx.target(&manifest, Path::new("hosts")),
+            Some(PathBuf::from("/stage/etc/hosts"))
+        );
+    }
+
+    #[test]
+    fn collapse_strips_target_root() {
+        let mut ctx = context();
+        ctx.target_root = Some("/stage".into());
+
+        assert_eq!(
+            ctx.collapse(Path::new("/stage/home/user/.vimrc")).unwrap(),
+            Path::new("~/.vimrc")
+        );
+    }
+
+    #[test]
+    fn resolves_entries_by_repo_path_or_target() {
+        let ctx = context();
+        let manifest = Manifest::parse(
+            r#"
+            "vimrc" = "~/.vimrc"
+            "nvim" = "~/.config/nvim"
+            "#,
+        )
+        .unwrap();
+        let resolve = |arg: &str| ctx.resolve(&manifest, Path::new(arg));
+
+        assert_eq!(resolve("vimrc").unwrap(), Path::new("vimrc"));
+        assert_eq!(resolve("/repo/vimrc").unwrap(), Path::new("vimrc"));
+        assert_eq!(resolve("~/.vimrc").unwrap(), Path::new("vimrc"));
+        assert_eq!(
+            resolve("/home/user/.config/nvim/").unwrap(),
+            Path::new("nvim")
+        );
+        assert!(matches!(resolve("~/.bashrc"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn reports_ambiguous_entries() {
+        let ctx = context();
+        let manifest = Manifest::parse(
+            r#"
+            "vimrc" = "~/.vimrc"
+            "vimrc.work" = "~/.vimrc"
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            ctx.resolve(&manifest, Path::new("~/.vimrc")),
+            Err(Error::AmbiguousEntry(..))
+        ));
+        assert_eq!(
+            ctx.resolve(&manifest, Path::new("vimrc.work")).unwrap(),
+            Path::new("vimrc.work")
+        );
+    }
+
+    #[test]
+    fn links_expand_unfolded_directories() {
+        let repo = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join("fish/functions")).unwrap();
+        fs::write(repo.path().join("fish/config.fish"), "").unwrap();
+        fs::write(repo.path().join("fish/functions/ls.fish"), "").unwrap();
+        fs::write(repo.path().join("fish/fish_variables"), "").unwrap();
+        fs::write(repo.path().join("vimrc"), "").unwrap();
+
+        let mut ctx = context();
+        ctx.repo = repo.path().to_path_buf();
+        let manifest = Manifest::parse(
+            r#"
+            vimrc = "~/.vimrc"
+
+            [fish]
+            target = "~/.config/fish"
+            dir = "unfold"
+            exclude = ["fish_variables"]
+            "#,
+        )
+        .unwrap();
+
+        let links: Vec<_> = ctx
+            .links(&manifest)
+            .unwrap()
+            .into_iter()
+            .map(|link| (link.file, link.target))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    PathBuf::from("fish/config.fish"),
+                    PathBuf::from("/home/user/.config/fish/config.fish")
+                ),
+                (
+                    PathBuf::from("fish/functions/ls.fish"),
+                    PathBuf::from("/home/user/.config/fish/functions/ls.fish")
+                ),
+                (PathBuf::from("vimrc"), PathBuf::from("/home/user/.vimrc")),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_only_include_active_profiles() {
+        let mut ctx = context();
+        let manifest = Manifest::parse(
+            r#"
+            version = 2
+
+            [profiles.server]
+            hosts = ["build-*"]
+            entries = ["vimrc"]
+
+            [files]
+            vimrc = "~/.vimrc"
+            kitty = "~/.config/kitty"
+            "#,
+        )
+        .unwrap();
+        let files = |ctx: &Context| -> Vec<PathBuf> {
+            let links = ctx.links(&manifest).unwrap();
+            links.into_iter().map(|link| link.file).collect()
+        };
+
+        assert_eq!(files(&ctx), ["kitty", "vimrc"].map(PathBuf::from));
+        ctx.facts = on_host(Some("build-01"));
+        assert_eq!(files(&ctx), [PathBuf::from("vimrc")]);
+        ctx.facts = on_host(None);
+        ctx.profiles = vec!["server".into()];
+        assert_eq!(files(&ctx), [PathBuf::from("vimrc")]);
+    }
+
+    #[test]
+    fn links_whose_condition_fails_are_skipped() {
+        let home = tempfile::TempDir::new().unwrap();
+        fs::create_dir(home.path().join(".config")).unwrap();
+        let mut ctx = context();
+        ctx.home = Some(home.path().to_path_buf());
+        let manifest = Manifest::parse(
+            r#"
+            version = 2
+
+            [files.sway]
+            target = "~/.config/sway"
+            os = "linux"
+            exists = "~/.config"
+
+            [files.kitty]
+            target = "~/.config/kitty"
+            dir = "unfold"
+            os = "macos"
+            "#,
+        )
+        .unwrap();
+
+        let links: Vec<_> = ctx
+            .links(&manifest)
+            .unwrap()
+            .into_iter()
+            .map(|link| (link.file, link.skipped))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (PathBuf::from("kitty"), true),
+                (PathBuf::from("sway"), false)
+            ]
+        );
+    }
+}
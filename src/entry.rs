@@ -0,0 +1,467 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize, de};
+
+use crate::condition::Condition;
+use crate::error::{Error, Result};
+
+/// How a tracked directory is deployed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DirMode {
+    /// A single symlink to the whole directory
+    #[default]
+    Fold,
+    /// A real directory at the target with one symlink per file
+    Unfold,
+}
+
+/// How a repository file is deployed to its target.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// A symlink pointing back into the repository
+    #[default]
+    Symlink,
+    /// An independent copy whose drift is tracked by content hash
+    Copy,
+    /// A hard link to the repository file, or a copy across filesystems
+    Hardlink,
+    /// The repository file rendered with template variables; write a
+    /// literal `{{` as `{{ "{{" }}`
+    Template,
+    /// Ciphertext in the repository, decrypted to a private file
+    Encrypted,
+}
+
+impl LinkMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A manifest entry: where a repository path deploys to, plus options.
+///
+/// Entries without options are written in the `"name" = "target"`
+/// shorthand; anything else becomes a table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawEntry", into = "RawEntry")]
+pub struct Entry {
+    pub target: PathBuf,
+    pub mode: LinkMode,
+    /// Link with a path relative to the target's directory, overriding the
+    /// manifest default
+    pub relative: Option<bool>,
+    /// Labels that profiles use to select entries
+    pub tags: Vec<String>,
+    /// Machines the entry deploys to; others skip it
+    pub condition: Condition,
+    /// Permission bits of the deployed file, e.g. `0o600`
+    pub permissions: Option<u32>,
+    pub dir: Option<DirMode>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Entry {
+    pub fn new(target: PathBuf) -> Self {
+        Self {
+            target,
+            mode: LinkMode::default(),
+            relative: None,
+            tags: Vec::new(),
+            condition: Condition::default(),
+            permissions: None,
+            dir: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn is_unfolded(&self) -> bool {
+        self.dir == Some(DirMode::Unfold)
+    }
+
+    /// Permission bits the deployed file must have. Decrypted secrets are
+    /// never readable by anyone but the owner, whatever the manifest says.
+    pub fn deployed_permissions(&self) -> Option<u32> {
+        match self.mode {
+            LinkMode::Encrypted => Some(self.permissions.unwrap_or(0o600) & 0o600),
+            _ => self.permissions,
+        }
+    }
+
+    /// The first option the entry uses that version 1 manifests cannot
+    /// hold, if any.
+    pub fn version_2_option(&self) -> Option<&'static str> {
+        if self.mode != LinkMode::default() {
+            Some("mode")
+        } else if self.relative.is_some() {
+            Some("relative")
+        } else if !self.tags.is_empty() {
+            Some("tags")
+        } else if self.condition != Condition::default() {
+            Some("a condition")
+        } else if self.permissions.is_some() {
+            Some("permissions")
+        } else if self.dir.is_some() {
+            Some("dir")
+        } else if !self.include.is_empty() || !self.exclude.is_empty() {
+            Some("include/exclude")
+        } else {
+            None
+        }
+    }
+
+    /// Check that every glob the entry uses is valid.
+    pub fn validate(&self) -> Result<()> {
+        self.condition.validate()?;
+        compile(&self.include)?;
+        compile(&self.exclude)?;
+        Ok(())
+    }
+
+    /// Whether a path relative to the tracked directory is selected by the
+    /// include and exclude patterns. No include patterns means everything.
+    pub fn selects(&self, relative: &Path) -> Result<bool> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let matches = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .any(|p| p.matches_path_with(relative, options))
+        };
+
+        let include = compile(&self.include)?;
+        let exclude = compile(&self.exclude)?;
+        Ok((include.is_empty() || matches(&include)) && !matches(&exclude))
+    }
+
+    /// Files below `dir`, relative to it, that this entry selects.
+    pub fn selected_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut selected = Vec::new();
+        for file in files_under(dir)? {
+            if self.selects(&file)? {
+                selected.push(file);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Every non-directory below `dir`, relative to it, in sorted order.
+pub fn files_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Parse glob patterns, reporting the first invalid one.
+pub fn compile(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p).map_err(|e| Error::InvalidGlob(p.clone(), e.msg.to_string())))
+        .collect()
+}
+
+/// On-disk form of an entry: a bare target or a table of options.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RawEntry {
+    Target(PathBuf),
+    Table(Box<TableEntry>),
+}
+
+/// The table form of an entry. Conditions are listed inline rather than
+/// flattened so that misspelled keys are rejected instead of ignored.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TableEntry {
+    target: PathBuf,
+    #[serde(default, skip_serializing_if = "LinkMode::is_default")]
+    mode: LinkMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relative: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exists: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<FileMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dir: Option<DirMode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+}
+
+// Tries the string form first and then the table, so errors in a table
+// name the offending key instead of a generic untagged enum mismatch
+impl<'de> Deserialize<'de> for RawEntry {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = RawEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a target path or a table of entry options")
+            }
+
+            fn visit_str<E: de::Error>(self, target: &str) -> std::result::Result<RawEntry, E> {
+                Ok(RawEntry::Target(target.into()))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> std::result::Result<RawEntry, A::Error> {
+                let table = TableEntry::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(RawEntry::Table(Box::new(table)))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl From<RawEntry> for Entry {
+    fn from(raw: RawEntry) -> Self {
+        let table = match raw {
+            RawEntry::Target(target) => return Self::new(target),
+            RawEntry::Table(table) => *table,
+        };
+        Self {
+            target: table.target,
+            mode: table.mode,
+            relative: table.relative,
+            tags: table.tags,
+            condition: Condition {
+                os: table.os,
+                hostname: table.hostname,
+                env: table.env,
+                exists: table.exists,
+                command: table.command,
+            },
+            permissions: table.permissions.map(|p| p.0),
+            dir: table.dir,
+            include: table.include,
+            exclude: table.exclude,
+        }
+    }
+}
+
+impl From<Entry> for RawEntry {
+    fn from(entry: Entry) -> Self {
+        if entry == Entry::new(entry.target.clone()) {
+            return Self::Target(entry.target);
+        }
+        let Condition {
+            os,
+            hostname,
+            env,
+            exists,
+            command,
+        } = entry.condition;
+        Self::Table(Box::new(TableEntry {
+            target: entry.target,
+            mode: entry.mode,
+            relative: entry.relative,
+            tags: entry.tags,
+            os,
+            hostname,
+            env,
+            exists,
+            command,
+            permissions: entry.permissions.map(FileMode),
+            dir: entry.dir,
+            include: entry.include,
+            exclude: entry.exclude,
+        }))
+    }
+}
+
+/// Permission bits written as an octal string, e.g. `"0600"`, since TOML
+/// integers are decimal when saved.
+#[derive(Debug, Clone, Copy)]
+struct FileMode(u32);
+
+impl Serialize for FileMode {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04o}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        u32::from_str_radix(&text, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(Self)
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "invalid permissions {text:?}, expected octal like \"0644\""
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn unfolded(include: &[&str], exclude: &[&str]) -> Entry {
+        Entry {
+            dir: Some(DirMode::Unfold),
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+            ..Entry::new("~/.config/fish".into())
+        }
+    }
+
+    #[test]
+    fn selects_everything_without_patterns() {
+        let entry = unfolded(&[], &[]);
+        assert!(entry.selects(Path::new("config.fish")).unwrap());
+        assert!(entry.selects(Path::new("functions/ls.fish")).unwrap());
+    }
+
+    #[test]
+    fn include_and_exclude_patterns() {
+        let entry = unfolded(&["*.fish", "functions/**"], &["functions/cache*"]);
+        assert!(entry.selects(Path::new("config.fish")).unwrap());
+        assert!(entry.selects(Path::new("functions/ls.fish")).unwrap());
+        assert!(!entry.selects(Path::new("fish_variables")).unwrap());
+        assert!(!entry.selects(Path::new("functions/cache.db")).unwrap());
+    }
+
+    #[test]
+    fn star_does_not_cross_directories() {
+        let entry = unfolded(&["*.fish"], &[]);
+        assert!(!entry.selects(Path::new("conf.d/abbr.fish")).unwrap());
+    }
+
+    #[test]
+    fn invalid_pattern_errors() {
+        let entry = unfolded(&["[unclosed"], &[]);
+        assert!(matches!(entry.validate(), Err(Error::InvalidGlob(..))));
+    }
+
+    #[test]
+    fn permissions_are_octal_strings() {
+        let entry: Entry = toml::from_str::<toml::Table>(
+            "e = { target = \"~/.ssh/config\", permissions = \"0600\" }",
+        )
+        .unwrap()["e"]
+            .clone()
+            .try_into()
+            .unwrap();
+        assert_eq!(entry.permissions, Some(0o600));
+
+        let saved = toml::to_string(&entry).unwrap();
+        assert!(saved.contains("permissions = \"0600\""));
+
+        let invalid =
+            toml::from_str::<toml::Table>("e = { target = \"x\", permissions = \"0999\" }")
+                .unwrap()["e"]
+                .clone()
+                .try_into::<Entry>();
+        assert!(invalid.is_err());
+    }
+
+    fn parse(table: &str) -> std::result::Result<Entry, toml::de::Error> {
+        toml::from_str::<toml::Table>(table).unwrap()["e"]
+            .clone()
+            .try_into()
+    }
+
+    #[test]
+    fn rejects_misspelled_keys() {
+        let error = parse("e = { target = \"~/.gitconfig\", mdoe = \"copy\" }").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `mdoe`"),
+            "{error}"
+        );
+
+        let error = parse("e = { target = \"~/.ssh\", permisions = \"0700\" }").unwrap_err();
+        assert!(error.to_string().contains("permisions"), "{error}");
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let error = parse("e = { target = \"~/.gitconfig\", mode = \"cpoy\" }").unwrap_err();
+        assert!(
+            error.to_string().contains("unknown variant `cpoy`"),
+            "{error}"
+        );
+
+        assert!(parse("e = 42").is_err());
+    }
+
+    #[test]
+    fn conditions_roundtrip_inline() {
+        let entry = parse("e = { target = \"~/.config/sway\", os = \"linux\" }").unwrap();
+        assert_eq!(entry.condition.os.as_deref(), Some("linux"));
+
+        let saved = toml::to_string(&entry).unwrap();
+        assert!(saved.contains("os = \"linux\""), "{saved}");
+    }
+
+    #[test]
+    fn lists_selected_files_recursively() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("functions")).unwrap();
+        fs::write(dir.path().join("config.fish"), "").unwrap();
+        fs::write(dir.path().join("fish_variables"), "").unwrap();
+        fs::write(dir.path().join("functions/ls.fish"), "").unwrap();
+
+        let files = unfolded(&[], &["fish_variables"])
+            .selected_files(dir.path())
+            .unwrap();
+
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("config.fish"),
+                PathBuf::from("functions/ls.fish")
+            ]
+        );
+    }
+}
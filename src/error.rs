@@ -1,5 +1,8 @@
 use std::{io, path::PathBuf};
 
+use crate::manifest::{MANIFEST_FILE, MANIFEST_VERSION};
+use crate::secret::PASSPHRASE_VAR;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("I/O error: {0}")]
@@ -8,16 +11,73 @@ pub enum Error {
     TomlParse(#[from] toml::de::Error),
     #[error("Failed to serialize manifest: {0}")]
     TomlSerialize(#[from] toml::ser::Error),
+    #[error("Unsupported manifest version {0} (this dot understands up to {MANIFEST_VERSION})")]
+    UnsupportedManifestVersion(u32),
+    #[error("{0} needs manifest version {MANIFEST_VERSION} (run `dot migrate` first)")]
+    NeedsMigration(String),
     #[error("{0} not found")]
     NotFound(PathBuf),
+    #[error("{0} is ambiguous: it could mean the entry {1} or {2}")]
+    AmbiguousEntry(PathBuf, PathBuf, PathBuf),
     #[error("{0} already exists")]
     AlreadyExists(PathBuf),
     #[error("{0} is already tracked")]
     AlreadyTracked(PathBuf),
+    #[error("{0} must be a relative path inside the repository")]
+    InvalidRepoPath(PathBuf),
+    #[error("{0} is not a directory")]
+    NotADirectory(PathBuf),
+    #[error("Invalid glob pattern {0:?}: {1}")]
+    InvalidGlob(String, String),
+    #[error("{0} is not a regular file")]
+    NotAFile(PathBuf),
+    #[error("{0} is a socket, FIFO or device file and cannot be tracked")]
+    SpecialFile(PathBuf),
+    #[error("{0} was given more than once")]
+    DuplicatePath(PathBuf),
+    #[error("{0} and {1} would overlap at {2} in the repository")]
+    RepoPathCollision(PathBuf, PathBuf, PathBuf),
+    #[error("--as can only be used when adding a single path")]
+    RepoPathForMany,
+    #[error("Encryption failed: {0}")]
+    Encrypt(String),
+    #[error("Cannot decrypt {0}: {1}")]
+    Decrypt(PathBuf, String),
+    #[error("{0} does not contain an age identity (AGE-SECRET-KEY-...)")]
+    InvalidKeyFile(PathBuf),
+    #[error("No key for encrypted entries (pass --key-file or set {PASSPHRASE_VAR})")]
+    NoKey,
+    #[error("Editor exited with {0}; the secret was left unchanged")]
+    EditorFailed(std::process::ExitStatus),
+    #[error("Cannot render template {0}: {1}")]
+    Template(PathBuf, String),
+    #[error("{0} is not an encrypted entry")]
+    NotASecret(PathBuf),
+    #[error("{0} is not a template entry")]
+    NotATemplate(PathBuf),
+    #[error("Cannot adopt {0} into a template; edit the template in the repository instead")]
+    AdoptTemplate(PathBuf),
+    #[error("No profile named {0} in the manifest")]
+    UnknownProfile(String),
     #[error("Cannot determine home directory")]
     NoHomeDir,
     #[error("Expected {0} to be a symlink")]
     NotASymlink(PathBuf),
+    #[error("{0} points to {1}, not to the repository file")]
+    ForeignSymlink(PathBuf, PathBuf),
+    #[error(
+        "{0} was edited after it was deployed (run `dot sync --on-conflict adopt` to keep the changes)"
+    )]
+    ModifiedCopy(PathBuf),
+    #[error("No {MANIFEST_FILE} found in {0} or its parents (run `dot init` or pass --repo)")]
+    NotARepository(PathBuf),
+    #[error("{0} entries out of sync")]
+    OutOfSync(usize),
+    #[error("{error} (rollback failed: {rollback})")]
+    RollbackFailed {
+        error: Box<Error>,
+        rollback: Box<Error>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
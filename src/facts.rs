@@ -0,0 +1,128 @@
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+/// What conditions and profiles know about the machine they run on.
+///
+/// Commands read the host through this trait so tests can describe a
+/// different machine without touching the real one.
+pub trait Facts: fmt::Debug {
+    /// Operating system as named by Rust, e.g. `linux` or `macos`
+    fn os(&self) -> &str;
+    fn hostname(&self) -> Option<&str>;
+    /// Value of an environment variable, if set
+    fn env(&self, name: &str) -> Option<String>;
+    /// Whether an executable with this name is on `PATH`
+    fn has_command(&self, name: &str) -> bool;
+}
+
+/// The machine `dot` is running on.
+#[derive(Debug, Default)]
+pub struct SystemFacts {
+    hostname: Option<String>,
+}
+
+impl SystemFacts {
+    pub fn new() -> Self {
+        Self {
+            hostname: hostname(),
+        }
+    }
+}
+
+impl Facts for SystemFacts {
+    fn os(&self) -> &str {
+        std::env::consts::OS
+    }
+
+    fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    fn env(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+
+    fn has_command(&self, name: &str) -> bool {
+        if name.contains('/') {
+            return is_executable(Path::new(name));
+        }
+        std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| is_executable(&dir.join(name)))
+        })
+    }
+}
+
+/// A made-up machine for tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct FakeFacts {
+    pub os: String,
+    pub hostname: Option<String>,
+    pub env: std::collections::BTreeMap<String, String>,
+    pub commands: Vec<String>,
+}
+
+#[cfg(test)]
+impl Facts for FakeFacts {
+    fn os(&self) -> &str {
+        &self.os
+    }
+
+    fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    fn env(&self, name: &str) -> Option<String> {
+        self.env.get(name).cloned()
+    }
+
+    fn has_command(&self, name: &str) -> bool {
+        self.commands.iter().any(|c| c == name)
+    }
+}
+
+/// The machine's hostname, or `None` if it cannot be determined.
+fn hostname() -> Option<String> {
+    let from_file = ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok());
+    let from_command = || {
+        Command::new("hostname")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+    };
+
+    from_file
+        .or_else(from_command)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_commands_by_path() {
+        let dir = TempDir::new().unwrap();
+        let script = dir.path().join("tool");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        let facts = SystemFacts::new();
+        assert!(!facts.has_command(script.to_str().unwrap()));
+
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(facts.has_command(script.to_str().unwrap()));
+        assert!(facts.has_command("sh"));
+        assert!(!facts.has_command("definitely-not-a-command"));
+    }
+}
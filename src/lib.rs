@@ -1,5 +1,19 @@
 pub mod cli;
 pub mod commands;
+pub mod condition;
+pub mod conflict;
+pub mod context;
+pub mod entry;
 pub mod error;
+pub mod facts;
+pub mod link;
 pub mod manifest;
 pub mod path;
+pub mod plan;
+pub mod repo;
+pub mod secret;
+pub mod state;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod transfer;
@@ -0,0 +1,418 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::path::{canonicalize_existing, relative_path, to_lexical_absolute};
+use crate::state::{bytes_hash, content_hash};
+use crate::template::{Vars, render_file};
+
+/// Deployment state of a single manifest entry.
+#[derive(Debug, PartialEq)]
+pub enum LinkState {
+    /// The target is a symlink to the repository file
+    Linked,
+    /// Nothing exists at the target
+    Missing,
+    /// The repository file is gone
+    Broken,
+    /// A regular file or directory occupies the target
+    Conflict,
+    /// The target is a symlink to another location inside the repository
+    WrongTarget(PathBuf),
+    /// The target is a symlink to a location outside the repository
+    Unmanaged(PathBuf),
+    /// A copy or broken hard link that can be replaced without losing
+    /// anything, e.g. because only the repository file has changed
+    Outdated,
+    /// A copy or broken hard link that was edited after it was deployed
+    Modified,
+    /// The entry's condition does not hold on this machine
+    Skipped,
+    /// Deployed correctly, but with these permission bits instead of the
+    /// ones in the manifest
+    WrongPermissions(u32),
+}
+
+impl LinkState {
+    /// Classify the target of an entry without modifying anything.
+    pub fn inspect(local: &Path, target: &Path, repo: &Path) -> Result<Self> {
+        if local.symlink_metadata().is_err() {
+            return Ok(Self::Broken);
+        }
+
+        let metadata = match target.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::Missing),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !metadata.file_type().is_symlink() {
+            return Ok(Self::Conflict);
+        }
+
+        let destination = read_link_absolute(target)?;
+        if same_file(&destination, local) {
+            return Ok(Self::Linked);
+        }
+
+        let repo = repo.canonicalize()?;
+        let resolved = destination
+            .canonicalize()
+            .unwrap_or_else(|_| destination.clone());
+        if resolved.starts_with(&repo) {
+            Ok(Self::WrongTarget(destination))
+        } else {
+            Ok(Self::Unmanaged(destination))
+        }
+    }
+
+    /// Classify a copied target by comparing content hashes.
+    ///
+    /// `recorded` is the hash the target had when it was deployed; without
+    /// it a differing copy counts as modified, since replacing it could
+    /// lose edits.
+    pub fn inspect_copy(local: &Path, target: &Path, recorded: Option<&str>) -> Result<Self> {
+        if let Some(state) = Self::inspect_file(local, target)? {
+            return Ok(state);
+        }
+
+        Self::compare_hashes(&content_hash(local)?, target, recorded)
+    }
+
+    /// Classify a rendered template by comparing the target with a fresh
+    /// rendering; a target that matches neither it nor the recorded hash
+    /// was edited by hand.
+    pub fn inspect_template(
+        local: &Path,
+        target: &Path,
+        vars: &Vars,
+        recorded: Option<&str>,
+    ) -> Result<Self> {
+        if let Some(state) = Self::inspect_file(local, target)? {
+            return Ok(state);
+        }
+
+        let rendered = render_file(local, vars)?;
+        Self::compare_hashes(&bytes_hash(rendered.as_bytes()), target, recorded)
+    }
+
+    /// Classify a decrypted secret from recorded hashes alone, so checking
+    /// status never needs the key: an edited target is modified, and a
+    /// changed repository file makes it outdated.
+    pub fn inspect_encrypted(
+        local: &Path,
+        target: &Path,
+        recorded: Option<&str>,
+        recorded_source: Option<&str>,
+    ) -> Result<Self> {
+        if let Some(state) = Self::inspect_file(local, target)? {
+            return Ok(state);
+        }
+
+        if recorded != Some(content_hash(target)?.as_str()) {
+            Ok(Self::Modified)
+        } else if recorded_source != Some(content_hash(local)?.as_str()) {
+            Ok(Self::Outdated)
+        } else {
+            Ok(Self::Linked)
+        }
+    }
+
+    /// Compare a deployed file with the hash it should have and the one it
+    /// had when it was deployed.
+    fn compare_hashes(expected: &str, target: &Path, recorded: Option<&str>) -> Result<Self> {
+        let target_hash = content_hash(target)?;
+        if target_hash == expected {
+            Ok(Self::Linked)
+        } else if recorded == Some(target_hash.as_str()) {
+            Ok(Self::Outdated)
+        } else {
+            Ok(Self::Modified)
+        }
+    }
+
+    /// Classify a hard-linked target by comparing device and inode numbers.
+    ///
+    /// A target that is a separate file, e.g. because an application saved
+    /// it by replacing it, is outdated if its contents still match and
+    /// modified otherwise.
+    pub fn inspect_hardlink(local: &Path, target: &Path) -> Result<Self> {
+        if let Some(state) = Self::inspect_file(local, target)? {
+            return Ok(state);
+        }
+
+        if same_inode(local, target)? {
+            Ok(Self::Linked)
+        } else if content_hash(local)? == content_hash(target)? {
+            Ok(Self::Outdated)
+        } else {
+            Ok(Self::Modified)
+        }
+    }
+
+    /// States shared by deployments that put a real file at the target, or
+    /// `None` when both sides exist and their contents must be compared.
+    fn inspect_file(local: &Path, target: &Path) -> Result<Option<Self>> {
+        if local.symlink_metadata().is_err() {
+            return Ok(Some(Self::Broken));
+        }
+
+        let metadata = match target.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Self::Missing)),
+            Err(e) => return Err(e.into()),
+        };
+
+        if metadata.file_type().is_symlink() {
+            // A symlink to the repository file, left from symlink mode
+            let destination = read_link_absolute(target)?;
+            if same_file(&destination, local) {
+                return Ok(Some(Self::Outdated));
+            }
+            return Ok(Some(Self::Conflict));
+        }
+        if metadata.is_dir() != local.is_dir() {
+            return Ok(Some(Self::Conflict));
+        }
+        Ok(None)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Self::Linked | Self::Skipped)
+    }
+}
+
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Self::Linked => "linked",
+            Self::Missing => "missing link",
+            Self::Broken => "broken",
+            Self::Conflict => "conflict",
+            Self::WrongTarget(_) => "wrong target",
+            Self::Unmanaged(_) => "unmanaged symlink",
+            Self::Outdated => "outdated",
+            Self::Modified => "modified",
+            Self::Skipped => "skipped (condition)",
+            Self::WrongPermissions(_) => "wrong permissions",
+        };
+        f.pad(label)
+    }
+}
+
+/// What a symlink at `link` should contain to point at `local`: its
+/// canonical absolute path, or with `relative` a path from the link's
+/// directory, which keeps working when both move together.
+pub fn symlink_source(local: &Path, link: &Path, relative: bool) -> Result<PathBuf> {
+    let original = canonicalize_existing(local)?;
+    if !relative {
+        return Ok(original);
+    }
+    let parent = link.parent().unwrap_or(Path::new(""));
+    Ok(relative_path(&original, &canonicalize_existing(parent)?))
+}
+
+/// Whether two paths are the same inode on the same device.
+pub fn same_inode(a: &Path, b: &Path) -> Result<bool> {
+    let a = a.symlink_metadata()?;
+    let b = b.symlink_metadata()?;
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+/// Whether `target`, or the closest ancestor of it that exists, is on the
+/// same filesystem as `local`, i.e. whether `local` can be hard-linked there.
+pub fn same_device(local: &Path, target: &Path) -> Result<bool> {
+    let device = local.symlink_metadata()?.dev();
+    for ancestor in target.ancestors() {
+        if let Ok(metadata) = ancestor.symlink_metadata() {
+            return Ok(metadata.dev() == device);
+        }
+    }
+    Ok(false)
+}
+
+/// Read a symlink, resolving a relative destination against the link's parent.
+pub fn read_link_absolute(link: &Path) -> Result<PathBuf> {
+    let destination = fs::read_link(link)?;
+    if destination.is_absolute() {
+        return Ok(destination);
+    }
+    let parent = link.parent().unwrap_or(Path::new(""));
+    to_lexical_absolute(&parent.join(destination))
+}
+
+/// Whether the symlink at `link` points inside `repo`, even to a file that
+/// no longer exists.
+pub fn points_into(link: &Path, repo: &Path) -> Result<bool> {
+    let destination = canonicalize_existing(&read_link_absolute(link)?)?;
+    Ok(destination.starts_with(repo.canonicalize()?))
+}
+
+/// Whether two paths resolve to the same file.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+    use std::os::unix::fs::symlink;
+
+    impl Fixture {
+        /// A repository holding `myfile`, deployed to `~/.myfile`
+        fn with_file() -> Self {
+            let fixture = Self::new();
+            fs::write(fixture.local(), "content").unwrap();
+            fixture
+        }
+
+        fn local(&self) -> PathBuf {
+            self.repo.path().join("myfile")
+        }
+
+        fn target(&self) -> PathBuf {
+            self.home.path().join(".myfile")
+        }
+
+        fn inspect(&self) -> LinkState {
+            LinkState::inspect(&self.local(), &self.target(), self.repo.path()).unwrap()
+        }
+    }
+
+    #[test]
+    fn linked() {
+        let f = Fixture::with_file();
+        symlink(f.local().canonicalize().unwrap(), f.target()).unwrap();
+        assert_eq!(f.inspect(), LinkState::Linked);
+    }
+
+    #[test]
+    fn linked_relative() {
+        let f = Fixture::with_file();
+        let relative = Path::new("..")
+            .join(f.repo.path().file_name().unwrap())
+            .join("myfile");
+        symlink(relative, f.target()).unwrap();
+        assert_eq!(f.inspect(), LinkState::Linked);
+    }
+
+    #[test]
+    fn missing() {
+        let f = Fixture::with_file();
+        assert_eq!(f.inspect(), LinkState::Missing);
+    }
+
+    #[test]
+    fn broken() {
+        let f = Fixture::with_file();
+        symlink(f.local(), f.target()).unwrap();
+        fs::remove_file(f.local()).unwrap();
+        assert_eq!(f.inspect(), LinkState::Broken);
+    }
+
+    #[test]
+    fn conflict() {
+        let f = Fixture::with_file();
+        fs::write(f.target(), "blocking").unwrap();
+        assert_eq!(f.inspect(), LinkState::Conflict);
+    }
+
+    #[test]
+    fn wrong_target_inside_repo() {
+        let f = Fixture::with_file();
+        let other = f.repo.path().join("other");
+        fs::write(&other, "other").unwrap();
+        symlink(&other, f.target()).unwrap();
+        assert_eq!(f.inspect(), LinkState::WrongTarget(other));
+    }
+
+    #[test]
+    fn unmanaged_outside_repo() {
+        let f = Fixture::with_file();
+        let elsewhere = f.home.path().join("elsewhere");
+        symlink(&elsewhere, f.target()).unwrap();
+        assert_eq!(f.inspect(), LinkState::Unmanaged(elsewhere));
+    }
+
+    #[test]
+    fn inspect_copy_compares_hashes() {
+        let f = Fixture::with_file();
+        let (local, target) = (f.local(), f.target());
+        assert_eq!(
+            LinkState::inspect_copy(&local, &target, None).unwrap(),
+            LinkState::Missing
+        );
+
+        fs::copy(&local, &target).unwrap();
+        let deployed = content_hash(&target).unwrap();
+        assert_eq!(
+            LinkState::inspect_copy(&local, &target, Some(&deployed)).unwrap(),
+            LinkState::Linked
+        );
+
+        fs::write(&local, "new").unwrap();
+        assert_eq!(
+            LinkState::inspect_copy(&local, &target, Some(&deployed)).unwrap(),
+            LinkState::Outdated
+        );
+
+        fs::write(&target, "edited").unwrap();
+        assert_eq!(
+            LinkState::inspect_copy(&local, &target, Some(&deployed)).unwrap(),
+            LinkState::Modified
+        );
+        assert_eq!(
+            LinkState::inspect_copy(&local, &target, None).unwrap(),
+            LinkState::Modified
+        );
+    }
+
+    #[test]
+    fn inspect_hardlink_compares_inodes() {
+        let f = Fixture::with_file();
+        let (local, target) = (f.local(), f.target());
+        fs::hard_link(&local, &target).unwrap();
+        assert_eq!(
+            LinkState::inspect_hardlink(&local, &target).unwrap(),
+            LinkState::Linked
+        );
+
+        // Saving by replacing the file breaks the link
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, "content").unwrap();
+        assert_eq!(
+            LinkState::inspect_hardlink(&local, &target).unwrap(),
+            LinkState::Outdated
+        );
+
+        fs::write(&target, "edited").unwrap();
+        assert_eq!(
+            LinkState::inspect_hardlink(&local, &target).unwrap(),
+            LinkState::Modified
+        );
+    }
+
+    #[test]
+    fn same_device_checks_closest_existing_ancestor() {
+        let f = Fixture::with_file();
+        let nested = f.home.path().join("a/b/c");
+        assert!(same_device(&f.local(), &nested).unwrap());
+    }
+
+    #[test]
+    fn relative_symlink_source_is_linked() {
+        let f = Fixture::with_file();
+        let source = symlink_source(&f.local(), &f.target(), true).unwrap();
+        assert!(source.is_relative());
+
+        symlink(&source, f.target()).unwrap();
+        assert_eq!(f.inspect(), LinkState::Linked);
+    }
+}
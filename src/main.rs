@@ -1,11 +1,5 @@
-mod cli;
-mod commands;
-mod error;
-mod manifest;
-mod path;
-
 fn main() {
-    if let Err(e) = cli::run() {
+    if let Err(e) = dot::cli::run() {
         eprintln!("\x1b[31mError: {e}\x1b[0m");
         std::process::exit(1);
     }
@@ -2,21 +2,90 @@ use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+
+use crate::entry::{Entry, compile};
 use crate::error::{Error, Result};
 use crate::path::{collapse_tilde_with_home, expand_tilde_with_home};
+use crate::transfer::write_atomic;
 
 pub const MANIFEST_FILE: &str = "dot.toml";
 
-#[derive(Debug, Default)]
+/// Schema version written by this build.
+///
+/// Version 1 is the original flat `"name" = "target"` file without a
+/// `version` key; version 2 nests entries under `[files]`.
+pub const MANIFEST_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
 pub struct Manifest {
-    entries: BTreeMap<PathBuf, PathBuf>,
+    /// Schema version the manifest was read with
+    version: u32,
+    defaults: Defaults,
+    profiles: BTreeMap<String, Profile>,
+    /// Custom variables available to templates
+    vars: BTreeMap<String, String>,
+    entries: BTreeMap<PathBuf, Entry>,
 }
 
-impl Manifest {
-    pub fn load() -> Result<Self> {
-        Self::load_from(Path::new(MANIFEST_FILE))
+/// A named set of entries that only some machines deploy.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Hostname globs that select the profile when no `--profile` is given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// Repository paths of the entries in the profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<PathBuf>,
+    /// Entries carrying any of these tags are in the profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Profile {
+    pub fn includes(&self, file: &Path, entry: &Entry) -> bool {
+        self.entries.iter().any(|e| e == file) || entry.tags.iter().any(|t| self.tags.contains(t))
+    }
+
+    pub fn matches_host(&self, hostname: &str) -> Result<bool> {
+        Ok(compile(&self.hosts)?.iter().any(|p| p.matches(hostname)))
+    }
+}
+
+/// Repository-wide settings that entries can override.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Defaults {
+    /// Create symlinks relative to the target's directory
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub relative: bool,
+}
+
+impl Defaults {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
     }
+}
 
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// On-disk layout of a versioned manifest.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    defaults: Defaults,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    vars: BTreeMap<String, String>,
+    #[serde(default)]
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+impl Manifest {
     pub fn load_from(path: &Path) -> Result<Self> {
         if path.exists() {
             let content = fs::read_to_string(path)?;
@@ -26,26 +95,141 @@ impl Manifest {
         }
     }
 
+    /// Parse a manifest of any supported version.
     pub fn parse(content: &str) -> Result<Self> {
-        let entries: BTreeMap<PathBuf, PathBuf> = toml::from_str(content)?;
-        Ok(Self { entries })
+        let mut table: toml::Table = toml::from_str(content)?;
+
+        // Version 1 files have no version key, and a v1 entry named
+        // "version" would map to a path string rather than an integer
+        let version = match table.get("version") {
+            Some(toml::Value::Integer(version)) => u32::try_from(*version).unwrap_or(u32::MAX),
+            _ => 1,
+        };
+
+        if version == 1 {
+            if table.get("version").is_some_and(toml::Value::is_integer) {
+                table.remove("version");
+            }
+            let entries = toml::Value::Table(table).try_into()?;
+            return Ok(Self {
+                version: 1,
+                defaults: Defaults::default(),
+                profiles: BTreeMap::new(),
+                vars: BTreeMap::new(),
+                entries,
+            });
+        }
+        if version > MANIFEST_VERSION || version == 0 {
+            return Err(Error::UnsupportedManifestVersion(version));
+        }
+
+        let document: Document = toml::Value::Table(table).try_into()?;
+        Ok(Self {
+            version: document.version,
+            defaults: document.defaults,
+            profiles: document.profiles,
+            vars: document.vars,
+            entries: document.files,
+        })
     }
 
     pub fn empty() -> Self {
-        Self::default()
+        Self {
+            version: MANIFEST_VERSION,
+            defaults: Defaults::default(),
+            profiles: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Schema version the manifest was read with, and is saved with.
+    pub fn version(&self) -> u32 {
+        self.version
     }
 
-    pub fn save(&self) -> Result<()> {
-        self.save_to(Path::new(MANIFEST_FILE))
+    /// Save in the current schema from now on.
+    pub fn upgrade(&mut self) {
+        self.version = MANIFEST_VERSION;
     }
 
     pub fn save_to(&self, path: &Path) -> Result<()> {
-        let content = self.serialize()?;
-        fs::write(path, content)?;
-        Ok(())
+        write_atomic(path, &self.serialize()?)
     }
 
+    /// Whether `entry` is linked with a relative path.
+    pub fn is_relative(&self, entry: &Entry) -> bool {
+        entry.relative.unwrap_or(self.defaults.relative)
+    }
+
+    pub fn vars(&self) -> &BTreeMap<String, String> {
+        &self.vars
+    }
+
+    /// Profiles that decide what this machine deploys: the `requested`
+    /// ones, or else every profile whose hosts match `hostname`. An empty
+    /// result means every entry is deployed.
+    pub fn active_profiles(
+        &self,
+        requested: &[String],
+        hostname: Option<&str>,
+    ) -> Result<Vec<&Profile>> {
+        if !requested.is_empty() {
+            return requested
+                .iter()
+                .map(|name| {
+                    self.profiles
+                        .get(name)
+                        .ok_or_else(|| Error::UnknownProfile(name.clone()))
+                })
+                .collect();
+        }
+
+        let Some(hostname) = hostname else {
+            return Ok(Vec::new());
+        };
+        let mut active = Vec::new();
+        for profile in self.profiles.values() {
+            if profile.matches_host(hostname)? {
+                active.push(profile);
+            }
+        }
+        Ok(active)
+    }
+
+    /// The manifest as TOML in the version it was read with. Version 1
+    /// manifests that use a newer feature are refused rather than upgraded
+    /// behind the user's back.
     pub fn serialize(&self) -> Result<String> {
+        if self.version == 1 {
+            return self.serialize_version_1();
+        }
+        let document = Document {
+            version: self.version,
+            defaults: self.defaults.clone(),
+            profiles: self.profiles.clone(),
+            vars: self.vars.clone(),
+            files: self.entries.clone(),
+        };
+        toml::to_string(&document).map_err(Error::TomlSerialize)
+    }
+
+    fn serialize_version_1(&self) -> Result<String> {
+        let needs = |feature: String| Err(Error::NeedsMigration(feature));
+        if !self.defaults.is_empty() {
+            return needs("[defaults]".into());
+        }
+        if !self.profiles.is_empty() {
+            return needs("[profiles]".into());
+        }
+        if !self.vars.is_empty() {
+            return needs("[vars]".into());
+        }
+        for (file, entry) in &self.entries {
+            if let Some(option) = entry.version_2_option() {
+                return needs(format!("{} with {option}", file.display()));
+            }
+        }
         toml::to_string(&self.entries).map_err(Error::TomlSerialize)
     }
 
@@ -60,7 +244,11 @@ impl Manifest {
     pub fn get_with_home(&self, file: &Path, home: Option<PathBuf>) -> Option<PathBuf> {
         self.entries
             .get(file)
-            .and_then(|p| expand_tilde_with_home(p, home).ok())
+            .and_then(|e| expand_tilde_with_home(&e.target, home).ok())
+    }
+
+    pub fn entry(&self, file: &Path) -> Option<&Entry> {
+        self.entries.get(file)
     }
 
     pub fn insert(&mut self, file: PathBuf, target: &Path) -> Result<()> {
@@ -74,14 +262,36 @@ impl Manifest {
         home: Option<PathBuf>,
     ) -> Result<()> {
         let tilde_path = collapse_tilde_with_home(target, home)?;
-        self.entries.insert(file, tilde_path);
+        self.entries.insert(file, Entry::new(tilde_path));
         Ok(())
     }
 
+    /// Insert an entry whose target is already in its stored form.
+    pub fn insert_entry(&mut self, file: PathBuf, entry: Entry) {
+        self.entries.insert(file, entry);
+    }
+
     pub fn remove(&mut self, file: &Path) -> bool {
         self.entries.remove(file).is_some()
     }
 
+    /// Move the entry at `from` to `to`, keeping it in the profiles that
+    /// list it by name.
+    pub fn rename(&mut self, from: &Path, to: PathBuf) -> bool {
+        let Some(entry) = self.entries.remove(from) else {
+            return false;
+        };
+        for profile in self.profiles.values_mut() {
+            for listed in &mut profile.entries {
+                if listed == from {
+                    *listed = to.clone();
+                }
+            }
+        }
+        self.entries.insert(to, entry);
+        true
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&Path, Result<PathBuf>)> + '_ {
         self.iter_with_home(dirs::home_dir())
     }
@@ -92,10 +302,9 @@ impl Manifest {
     ) -> impl Iterator<Item = (&Path, Result<PathBuf>)> + '_ {
         self.entries
             .iter()
-            .map(move |(k, v)| (k.as_path(), expand_tilde_with_home(v, home.clone())))
+            .map(move |(k, v)| (k.as_path(), expand_tilde_with_home(&v.target, home.clone())))
     }
 
-    #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         self.entries.is_empty()
     }
@@ -104,6 +313,7 @@ impl Manifest {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::entry::{DirMode, LinkMode};
 
     #[test]
     fn empty_manifest() {
@@ -131,11 +341,279 @@ mod tests {
     #[test]
     fn serialize_roundtrip() {
         let mut m = Manifest::empty();
-        m.entries.insert("a".into(), "~/b".into());
+        m.entries.insert("a".into(), Entry::new("~/b".into()));
         let parsed = Manifest::parse(&m.serialize().unwrap()).unwrap();
         assert!(parsed.contains(Path::new("a")));
     }
 
+    #[test]
+    fn parse_directory_entry() {
+        let m = Manifest::parse(
+            r#"
+            [fish]
+            target = "~/.config/fish"
+            dir = "unfold"
+            exclude = ["fish_variables"]
+            "#,
+        )
+        .unwrap();
+        let entry = m.entry(Path::new("fish")).unwrap();
+        assert_eq!(entry.dir, Some(DirMode::Unfold));
+        assert_eq!(entry.exclude, ["fish_variables"]);
+    }
+
+    #[test]
+    fn serialize_plain_entries_as_shorthand() {
+        let mut m = Manifest::empty();
+        m.insert_entry("vimrc".into(), Entry::new("~/.vimrc".into()));
+        assert_eq!(
+            m.serialize().unwrap(),
+            "version = 2\n\n[files]\nvimrc = \"~/.vimrc\"\n"
+        );
+    }
+
+    #[test]
+    fn parse_versioned_manifest() {
+        let m = Manifest::parse(
+            r#"
+            version = 2
+
+            [files]
+            vimrc = "~/.vimrc"
+
+            [files."nvim/init.lua"]
+            target = "~/.config/nvim/init.lua"
+            mode = "symlink"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(m.version(), 2);
+        assert_eq!(
+            m.entry(Path::new("nvim/init.lua")),
+            Some(&Entry::new("~/.config/nvim/init.lua".into()))
+        );
+        assert!(m.contains(Path::new("vimrc")));
+    }
+
+    #[test]
+    fn entry_overrides_relative_default() {
+        let m = Manifest::parse(
+            r#"
+            version = 2
+
+            [defaults]
+            relative = true
+
+            [files]
+            vimrc = "~/.vimrc"
+
+            [files.zshrc]
+            target = "~/.zshrc"
+            relative = false
+            "#,
+        )
+        .unwrap();
+        assert!(m.is_relative(m.entry(Path::new("vimrc")).unwrap()));
+        assert!(!m.is_relative(m.entry(Path::new("zshrc")).unwrap()));
+
+        let saved = Manifest::parse(&m.serialize().unwrap()).unwrap();
+        assert!(saved.defaults.relative);
+    }
+
+    const PROFILES: &str = r#"
+        version = 2
+
+        [profiles.server]
+        hosts = ["build-*"]
+        entries = ["vimrc"]
+
+        [profiles.desktop]
+        hosts = ["laptop"]
+        tags = ["gui"]
+
+        [files]
+        vimrc = "~/.vimrc"
+
+        [files."config/kitty"]
+        target = "~/.config/kitty"
+        tags = ["gui"]
+    "#;
+
+    fn profile_includes(m: &Manifest, profile: &Profile, file: &str) -> bool {
+        profile.includes(Path::new(file), m.entry(Path::new(file)).unwrap())
+    }
+
+    #[test]
+    fn profiles_select_by_entry_or_tag() {
+        let m = Manifest::parse(PROFILES).unwrap();
+        let server = &m.active_profiles(&["server".into()], None).unwrap()[0];
+        assert!(profile_includes(&m, server, "vimrc"));
+        assert!(!profile_includes(&m, server, "config/kitty"));
+
+        let desktop = &m.active_profiles(&["desktop".into()], None).unwrap()[0];
+        assert!(profile_includes(&m, desktop, "config/kitty"));
+        assert!(!profile_includes(&m, desktop, "vimrc"));
+    }
+
+    #[test]
+    fn profiles_are_selected_by_hostname() {
+        let m = Manifest::parse(PROFILES).unwrap();
+        let active = m.active_profiles(&[], Some("build-07")).unwrap();
+        assert_eq!(active, [&m.profiles["server"]]);
+        assert!(m.active_profiles(&[], Some("nas")).unwrap().is_empty());
+        // An explicit profile wins over the hostname
+        let active = m
+            .active_profiles(&["desktop".into()], Some("build-07"))
+            .unwrap();
+        assert_eq!(active, [&m.profiles["desktop"]]);
+    }
+
+    #[test]
+    fn unknown_profile_errors() {
+        let m = Manifest::parse(PROFILES).unwrap();
+        let result = m.active_profiles(&["nope".into()], None);
+        assert!(matches!(result, Err(Error::UnknownProfile(_))));
+    }
+
+    #[test]
+    fn rename_keeps_profile_membership() {
+        let mut m = Manifest::parse(PROFILES).unwrap();
+        assert!(m.rename(Path::new("vimrc"), "vim/vimrc".into()));
+
+        assert!(m.entry(Path::new("vimrc")).is_none());
+        assert!(profile_includes(&m, &m.profiles["server"], "vim/vimrc"));
+        assert!(!m.rename(Path::new("vimrc"), "vimrc.bak".into()));
+    }
+
+    #[test]
+    fn conditions_are_entry_keys() {
+        let content = "version = 2\n\n[files.sway]\ntarget = \"~/.config/sway\"\nos = \"linux\"\nenv = \"WAYLAND_DISPLAY\"\n";
+        let m = Manifest::parse(content).unwrap();
+        let condition = &m.entry(Path::new("sway")).unwrap().condition;
+        assert_eq!(condition.os.as_deref(), Some("linux"));
+        assert_eq!(condition.env.as_deref(), Some("WAYLAND_DISPLAY"));
+        assert_eq!(m.serialize().unwrap(), content);
+    }
+
+    #[test]
+    fn profiles_survive_saving() {
+        let m = Manifest::parse(PROFILES).unwrap();
+        let saved = Manifest::parse(&m.serialize().unwrap()).unwrap();
+        assert_eq!(saved.profiles, m.profiles);
+    }
+
+    #[test]
+    fn parse_legacy_manifest_as_version_1() {
+        let m = Manifest::parse(
+            r#"
+            "version" = "~/version"
+            "vimrc" = "~/.vimrc"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(m.version(), 1);
+        assert!(m.contains(Path::new("version")));
+        assert!(m.contains(Path::new("vimrc")));
+    }
+
+    #[test]
+    fn saving_keeps_legacy_version() {
+        let mut legacy = Manifest::parse(r#""vimrc" = "~/.vimrc""#).unwrap();
+        legacy.insert_entry("gitconfig".into(), Entry::new("~/.gitconfig".into()));
+
+        let saved = legacy.serialize().unwrap();
+
+        assert!(!saved.contains("version"));
+        let reparsed = Manifest::parse(&saved).unwrap();
+        assert_eq!(reparsed.version(), 1);
+        assert!(reparsed.contains(Path::new("vimrc")));
+        assert!(reparsed.contains(Path::new("gitconfig")));
+    }
+
+    #[test]
+    fn legacy_manifest_refuses_newer_features() {
+        let mut legacy = Manifest::parse(r#""vimrc" = "~/.vimrc""#).unwrap();
+        legacy.insert_entry(
+            "gitconfig".into(),
+            Entry {
+                mode: LinkMode::Copy,
+                ..Entry::new("~/.gitconfig".into())
+            },
+        );
+
+        assert!(matches!(legacy.serialize(), Err(Error::NeedsMigration(_))));
+        legacy.upgrade();
+        let upgraded = Manifest::parse(&legacy.serialize().unwrap()).unwrap();
+        assert_eq!(upgraded.version(), MANIFEST_VERSION);
+    }
+
+    #[test]
+    fn legacy_manifest_refuses_directory_options() {
+        let entries = [
+            (
+                "dir",
+                Entry {
+                    dir: Some(DirMode::Unfold),
+                    ..Entry::new("~/.config/fish".into())
+                },
+            ),
+            (
+                "include/exclude",
+                Entry {
+                    exclude: vec!["fish_variables".into()],
+                    ..Entry::new("~/.config/fish".into())
+                },
+            ),
+        ];
+        for (option, entry) in entries {
+            let mut legacy = Manifest::parse(r#""vimrc" = "~/.vimrc""#).unwrap();
+            legacy.insert_entry("fish".into(), entry);
+
+            let result = legacy.serialize();
+            assert!(
+                matches!(&result, Err(Error::NeedsMigration(f)) if f == &format!("fish with {option}")),
+                "{option}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in ["0", "3"] {
+            let result = Manifest::parse(&format!("version = {version}"));
+            assert!(
+                matches!(result, Err(Error::UnsupportedManifestVersion(_))),
+                "version {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_top_level_keys() {
+        for stray in ["\"vimrc\" = \"~/.vimrc\"\n", "[file]\n", "[default]\n"] {
+            let content =
+                format!("version = 2\n{stray}\n[files]\n\"gitconfig\" = \"~/.gitconfig\"\n");
+            assert!(Manifest::parse(&content).is_err(), "{stray}");
+        }
+    }
+
+    #[test]
+    fn serialize_directory_entry_roundtrip() {
+        let mut m = Manifest::empty();
+        let entry = Entry {
+            dir: Some(DirMode::Unfold),
+            include: vec!["*.fish".into()],
+            ..Entry::new("~/.config/fish".into())
+        };
+        m.insert_entry("config/fish".into(), entry.clone());
+        m.insert_entry("vimrc".into(), Entry::new("~/.vimrc".into()));
+
+        let parsed = Manifest::parse(&m.serialize().unwrap()).unwrap();
+
+        assert_eq!(parsed.entry(Path::new("config/fish")), Some(&entry));
+        assert!(parsed.contains(Path::new("vimrc")));
+    }
+
     #[test]
     fn insert_collapses_tilde() {
         let home = PathBuf::from("/home/user");
@@ -144,7 +622,7 @@ mod tests {
             .unwrap();
         assert_eq!(
             m.entries.get(Path::new("config")),
-            Some(&PathBuf::from("~/.config"))
+            Some(&Entry::new("~/.config".into()))
         );
     }
 
@@ -152,7 +630,8 @@ mod tests {
     fn get_expands_tilde() {
         let home = PathBuf::from("/home/user");
         let mut m = Manifest::empty();
-        m.entries.insert("config".into(), "~/.config".into());
+        m.entries
+            .insert("config".into(), Entry::new("~/.config".into()));
         assert_eq!(
             m.get_with_home(Path::new("config"), Some(home)),
             Some(PathBuf::from("/home/user/.config"))
@@ -162,7 +641,7 @@ mod tests {
     #[test]
     fn remove_existing() {
         let mut m = Manifest::empty();
-        m.entries.insert("a".into(), "b".into());
+        m.entries.insert("a".into(), Entry::new("b".into()));
         assert!(m.remove(Path::new("a")));
         assert!(!m.contains(Path::new("a")));
     }
@@ -1,16 +1,8 @@
 use std::path::{Component, Path, PathBuf};
 
 use crate::error::{Error, Result};
-
-#[allow(dead_code)]
-pub fn expand_tilde(path: &Path) -> Result<PathBuf> {
-    expand_tilde_with_home(path, dirs::home_dir())
-}
-
-#[allow(dead_code)]
-pub fn collapse_tilde(path: &Path) -> Result<PathBuf> {
-    collapse_tilde_with_home(path, dirs::home_dir())
-}
+use crate::manifest::MANIFEST_FILE;
+use crate::state::STATE_FILE;
 
 pub fn expand_tilde_with_home(path: &Path, home: Option<PathBuf>) -> Result<PathBuf> {
     if path.starts_with("~") {
@@ -34,6 +26,43 @@ pub fn collapse_tilde_with_home(path: &Path, home: Option<PathBuf>) -> Result<Pa
     }
 }
 
+/// Derive where a file is stored in the repository from its collapsed target,
+/// mirroring the directory structure: `~/.config/nvim/init.lua` becomes
+/// `config/nvim/init.lua` and `/etc/hosts` becomes `etc/hosts`.
+pub fn repo_path_for(target: &Path) -> PathBuf {
+    let mut components = target
+        .components()
+        .filter(|c| matches!(c, Component::Normal(n) if *n != "~"))
+        .map(Component::as_os_str);
+
+    let mut path = PathBuf::new();
+    if let Some(first) = components.next() {
+        // Drop the leading dot so the top-level entry is not hidden
+        let first = first.to_string_lossy();
+        path.push(
+            first
+                .strip_prefix('.')
+                .filter(|s| !s.is_empty())
+                .unwrap_or(&first),
+        );
+    }
+    path.extend(components);
+    path
+}
+
+/// Whether `path` is a plain relative path that stays inside the repository
+/// and clear of git's directory and the files `dot` keeps there itself.
+pub fn is_repo_relative(path: &Path) -> bool {
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Normal(first)) if first != ".git" => {}
+        _ => return false,
+    }
+    components.all(|c| matches!(c, Component::Normal(_)))
+        && path != Path::new(MANIFEST_FILE)
+        && path != Path::new(STATE_FILE)
+}
+
 pub fn to_lexical_absolute(path: &Path) -> Result<PathBuf> {
     let mut absolute = if path.is_absolute() {
         PathBuf::new()
@@ -53,6 +82,38 @@ pub fn to_lexical_absolute(path: &Path) -> Result<PathBuf> {
     Ok(absolute)
 }
 
+/// Canonicalize the longest existing prefix of `path` and append the rest,
+/// so paths that do not exist yet resolve through symlinked parents.
+pub fn canonicalize_existing(path: &Path) -> Result<PathBuf> {
+    let path = to_lexical_absolute(path)?;
+    for ancestor in path.ancestors() {
+        if let Ok(mut canonical) = ancestor.canonicalize() {
+            // Push component-wise: joining an empty rest adds a trailing slash
+            canonical.extend(path.strip_prefix(ancestor).expect("ancestor of path"));
+            return Ok(canonical);
+        }
+    }
+    Ok(path)
+}
+
+/// Express absolute `path` relative to absolute directory `base`, e.g.
+/// `/home/u/dotfiles/vimrc` from `/home/u` is `dotfiles/vimrc`.
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+    while let (Some(a), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+    }
+
+    let mut relative: PathBuf = base_components.map(|_| Component::ParentDir).collect();
+    relative.extend(path_components);
+    relative
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,6 +151,44 @@ mod tests {
         assert_eq!(result, PathBuf::from("/etc/config"));
     }
 
+    #[test]
+    fn repo_path_mirrors_home_structure() {
+        assert_eq!(
+            repo_path_for(Path::new("~/.config/nvim/init.lua")),
+            PathBuf::from("config/nvim/init.lua")
+        );
+        assert_eq!(repo_path_for(Path::new("~/.vimrc")), PathBuf::from("vimrc"));
+    }
+
+    #[test]
+    fn repo_path_keeps_nested_dots() {
+        assert_eq!(
+            repo_path_for(Path::new("~/.config/git/.gitignore")),
+            PathBuf::from("config/git/.gitignore")
+        );
+    }
+
+    #[test]
+    fn repo_path_for_absolute_target() {
+        assert_eq!(
+            repo_path_for(Path::new("/etc/hosts")),
+            PathBuf::from("etc/hosts")
+        );
+    }
+
+    #[test]
+    fn repo_relative_rejects_escapes() {
+        assert!(is_repo_relative(Path::new("nvim/init.lua")));
+        assert!(!is_repo_relative(Path::new("../init.lua")));
+        assert!(!is_repo_relative(Path::new("/init.lua")));
+        assert!(!is_repo_relative(Path::new("")));
+        assert!(!is_repo_relative(Path::new(".git/hooks/pre-commit")));
+        assert!(!is_repo_relative(Path::new("dot.toml")));
+        assert!(!is_repo_relative(Path::new(".dot-state.toml")));
+        assert!(is_repo_relative(Path::new("git/dot.toml")));
+        assert!(is_repo_relative(Path::new(".gitconfig")));
+    }
+
     #[test]
     fn lexical_absolute_resolves_parent() {
         let result = to_lexical_absolute(Path::new("/foo/bar/../baz")).unwrap();
@@ -101,4 +200,34 @@ mod tests {
         let result = to_lexical_absolute(Path::new("/foo/./bar")).unwrap();
         assert_eq!(result, PathBuf::from("/foo/bar"));
     }
+
+    #[test]
+    fn relative_path_from_sibling_and_nested_directories() {
+        assert_eq!(
+            relative_path(Path::new("/home/u/dotfiles/vimrc"), Path::new("/home/u")),
+            PathBuf::from("dotfiles/vimrc")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new("/home/u/dotfiles/config/nvim/init.lua"),
+                Path::new("/home/u/.config/nvim")
+            ),
+            PathBuf::from("../../dotfiles/config/nvim/init.lua")
+        );
+        assert_eq!(
+            relative_path(Path::new("/srv/dotfiles/hosts"), Path::new("/etc")),
+            PathBuf::from("../srv/dotfiles/hosts")
+        );
+    }
+
+    #[test]
+    fn canonicalize_existing_keeps_missing_suffix() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let canonical = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            canonicalize_existing(&dir.path().join("a/b")).unwrap(),
+            canonical.join("a/b")
+        );
+        assert_eq!(canonicalize_existing(dir.path()).unwrap(), canonical);
+    }
 }
@@ -0,0 +1,470 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt, symlink};
+use std::path::{Path, PathBuf};
+
+use crate::conflict::remove_path;
+use crate::error::{Error, Result};
+use crate::transfer::{copy_path, move_path};
+
+/// A single filesystem change that a command intends to make.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Create a directory and any missing parents
+    CreateDir(PathBuf),
+    /// Move a file or directory, across filesystems if needed
+    Move { from: PathBuf, to: PathBuf },
+    /// Create a symlink at `link` pointing to `original`
+    Symlink { original: PathBuf, link: PathBuf },
+    /// Copy a file or directory, keeping permissions and timestamps
+    Copy { from: PathBuf, to: PathBuf },
+    /// Create a hard link at `link` to `original`
+    Hardlink { original: PathBuf, link: PathBuf },
+    /// Create a file at `path` holding `content`, with permission bits
+    /// `mode` or the umask default
+    Write {
+        path: PathBuf,
+        content: Vec<u8>,
+        mode: Option<u32>,
+    },
+    /// Set the permission bits of a file or directory
+    SetPermissions { path: PathBuf, mode: u32 },
+    /// Remove a file, symlink or directory tree
+    Remove(PathBuf),
+}
+
+impl Action {
+    /// Apply the action, returning what is needed to revert it.
+    fn apply(&self) -> Result<Undo> {
+        match self {
+            Self::CreateDir(path) => {
+                let created: Vec<PathBuf> = path
+                    .ancestors()
+                    .take_while(|p| !p.as_os_str().is_empty() && p.symlink_metadata().is_err())
+                    .map(Path::to_path_buf)
+                    .collect();
+                fs::create_dir_all(path)?;
+                Ok(Undo::RemoveDirs(created))
+            }
+            Self::Move { from, to } => {
+                move_path(from, to)?;
+                Ok(Undo::Move {
+                    from: to.clone(),
+                    to: from.clone(),
+                })
+            }
+            Self::Symlink { original, link } => {
+                symlink(original, link)?;
+                Ok(Undo::RemoveLink(link.clone()))
+            }
+            Self::Copy { from, to } => {
+                copy_path(from, to)?;
+                Ok(Undo::RemoveCopy(to.clone()))
+            }
+            Self::Hardlink { original, link } => {
+                fs::hard_link(original, link)?;
+                Ok(Undo::RemoveLink(link.clone()))
+            }
+            Self::Write {
+                path,
+                content,
+                mode,
+            } => {
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                if let Some(mode) = mode {
+                    options.mode(*mode);
+                }
+                let mut file = options.open(path)?;
+                // The umask masks the mode given at creation
+                let written = file.write_all(content).and_then(|_| match mode {
+                    Some(mode) => file.set_permissions(fs::Permissions::from_mode(*mode)),
+                    None => Ok(()),
+                });
+                if let Err(e) = written {
+                    let _ = fs::remove_file(path);
+                    return Err(e.into());
+                }
+                Ok(Undo::RemoveCopy(path.clone()))
+            }
+            Self::SetPermissions { path, mode } => {
+                let previous = path.metadata()?.permissions().mode() & 0o7777;
+                fs::set_permissions(path, fs::Permissions::from_mode(*mode))?;
+                Ok(Undo::SetPermissions {
+                    path: path.clone(),
+                    mode: previous,
+                })
+            }
+            Self::Remove(path) => {
+                // Keep the removed file next to the original until commit
+                let trash = trash_path(path);
+                fs::rename(path, &trash)?;
+                Ok(Undo::Restore {
+                    trash,
+                    original: path.clone(),
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Self::Move { from, to } => write!(f, "move {} to {}", from.display(), to.display()),
+            Self::Symlink { original, link } => {
+                write!(f, "link {} -> {}", link.display(), original.display())
+            }
+            Self::Copy { from, to } => write!(f, "copy {} to {}", from.display(), to.display()),
+            Self::Hardlink { original, link } => {
+                write!(f, "hard link {} => {}", link.display(), original.display())
+            }
+            Self::Write { path, .. } => write!(f, "write {}", path.display()),
+            Self::SetPermissions { path, mode } => {
+                write!(f, "set permissions of {} to {mode:04o}", path.display())
+            }
+            Self::Remove(path) => write!(f, "remove {}", path.display()),
+        }
+    }
+}
+
+/// How to revert one applied action.
+#[derive(Debug)]
+enum Undo {
+    RemoveDirs(Vec<PathBuf>),
+    Move { from: PathBuf, to: PathBuf },
+    RemoveLink(PathBuf),
+    RemoveCopy(PathBuf),
+    SetPermissions { path: PathBuf, mode: u32 },
+    Restore { trash: PathBuf, original: PathBuf },
+}
+
+impl Undo {
+    fn revert(&self) -> Result<()> {
+        match self {
+            Self::RemoveDirs(dirs) => {
+                for dir in dirs {
+                    fs::remove_dir(dir)?;
+                }
+            }
+            Self::Move { from, to } => move_path(from, to)?,
+            Self::RemoveLink(link) => fs::remove_file(link)?,
+            Self::RemoveCopy(copy) => remove_path(copy)?,
+            Self::SetPermissions { path, mode } => {
+                fs::set_permissions(path, fs::Permissions::from_mode(*mode))?
+            }
+            Self::Restore { trash, original } => fs::rename(trash, original)?,
+        }
+        Ok(())
+    }
+}
+
+/// An ordered list of actions that is either previewed or executed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan {
+    actions: Vec<Action>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    /// Add every action of `other` after the ones already planned, leaving
+    /// out directories an earlier action already creates.
+    pub fn append(&mut self, other: Plan) {
+        for action in other.actions {
+            match action {
+                Action::CreateDir(dir) => self.ensure_dir(&dir),
+                action => self.push(action),
+            }
+        }
+    }
+
+    /// Plan to create `dir` unless it exists or an earlier action creates it.
+    pub fn ensure_dir(&mut self, dir: &Path) {
+        self.ensure_dir_with_mode(dir, None);
+    }
+
+    /// Like `ensure_dir`, also giving every directory it creates the
+    /// permission bits `mode`.
+    pub fn ensure_dir_with_mode(&mut self, dir: &Path, mode: Option<u32>) {
+        let planned = |actions: &[Action], dir: &Path| {
+            actions.iter().any(|action| match action {
+                Action::CreateDir(created) => created.starts_with(dir),
+                _ => false,
+            })
+        };
+        if planned(&self.actions, dir) || dir.exists() {
+            return;
+        }
+
+        let missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists() && !planned(&self.actions, p))
+            .map(Path::to_path_buf)
+            .collect();
+        self.push(Action::CreateDir(dir.to_path_buf()));
+        if let Some(mode) = mode {
+            for path in missing.into_iter().rev() {
+                self.push(Action::SetPermissions { path, mode });
+            }
+        }
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Apply every action in order. If one fails, the completed actions
+    /// are reverted before the error is returned.
+    ///
+    /// `check` runs before each action with its index and can abort the
+    /// plan, which lets tests inject a failure at any step.
+    pub fn begin_with(&self, mut check: impl FnMut(usize) -> Result<()>) -> Result<Transaction> {
+        let mut transaction = Transaction::default();
+
+        for (index, action) in self.actions.iter().enumerate() {
+            let applied = check(index).and_then(|_| action.apply());
+            match applied {
+                Ok(undo) => transaction.undo.push(undo),
+                Err(error) => return Err(transaction.rollback_after(error)),
+            }
+        }
+
+        Ok(transaction)
+    }
+}
+
+/// Applied actions that can still be reverted.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    undo: Vec<Undo>,
+}
+
+impl Transaction {
+    /// Make the changes permanent by deleting files kept for rollback.
+    pub fn commit(self) -> Result<()> {
+        for undo in self.undo {
+            if let Undo::Restore { trash, .. } = undo {
+                remove_path(&trash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Revert every applied action, most recent first.
+    pub fn rollback(self) -> Result<()> {
+        let mut first_error = None;
+        for undo in self.undo.iter().rev() {
+            if let Err(e) = undo.revert() {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Roll back after `error`, keeping both errors if the rollback fails too.
+    pub fn rollback_after(self, error: Error) -> Error {
+        match self.rollback() {
+            Ok(()) => error,
+            Err(rollback) => Error::RollbackFailed {
+                error: Box::new(error),
+                rollback: Box::new(rollback),
+            },
+        }
+    }
+}
+
+/// A `begin_with` check that fails before the action with index `step`, to
+/// exercise rollback.
+#[cfg(test)]
+pub fn fail_at(step: usize) -> impl FnMut(usize) -> Result<()> {
+    move |index| {
+        if index == step {
+            Err(std::io::Error::other("injected failure").into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Pick an unused hidden sibling path to park a removed file in.
+fn trash_path(path: &Path) -> PathBuf {
+    let mut counter = 0;
+    loop {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".dot-removed-{}", std::process::id()));
+        if counter > 0 {
+            name.push(format!(".{counter}"));
+        }
+
+        let candidate = path.with_file_name(name);
+        if candidate.symlink_metadata().is_err() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn executes_actions_in_order() {
+        let dir = TempDir::new().unwrap();
+        let nested = dir.path().join("a/b");
+        let file = dir.path().join("file");
+        fs::write(&file, "content").unwrap();
+
+        let mut plan = Plan::new();
+        plan.push(Action::CreateDir(nested.clone()));
+        plan.push(Action::Move {
+            from: file.clone(),
+            to: nested.join("file"),
+        });
+        plan.push(Action::Symlink {
+            original: nested.join("file"),
+            link: file.clone(),
+        });
+        plan.begin_with(|_| Ok(())).unwrap().commit().unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        assert!(file.symlink_metadata().unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn displays_actions() {
+        let action = Action::Symlink {
+            original: "/repo/vimrc".into(),
+            link: "/home/user/.vimrc".into(),
+        };
+        assert_eq!(action.to_string(), "link /home/user/.vimrc -> /repo/vimrc");
+    }
+
+    #[test]
+    fn stops_at_first_failure() {
+        let dir = TempDir::new().unwrap();
+        let created = dir.path().join("created");
+
+        let mut plan = Plan::new();
+        plan.push(Action::Remove(dir.path().join("missing")));
+        plan.push(Action::CreateDir(created.clone()));
+
+        assert!(plan.begin_with(|_| Ok(())).is_err());
+        assert!(!created.exists());
+    }
+
+    #[test]
+    fn rolls_back_completed_actions_at_every_step() {
+        for fail_at in 0..4 {
+            let dir = TempDir::new().unwrap();
+            let nested = dir.path().join("a/b");
+            let file = dir.path().join("file");
+            let old = dir.path().join("old");
+            fs::write(&file, "content").unwrap();
+            fs::write(&old, "old").unwrap();
+
+            let mut plan = Plan::new();
+            plan.push(Action::CreateDir(nested.clone()));
+            plan.push(Action::Move {
+                from: file.clone(),
+                to: nested.join("file"),
+            });
+            plan.push(Action::Remove(old.clone()));
+            plan.push(Action::Symlink {
+                original: nested.join("file"),
+                link: file.clone(),
+            });
+
+            let result = plan.begin_with(super::fail_at(fail_at));
+
+            assert!(result.is_err(), "step {fail_at}");
+            assert!(file.symlink_metadata().unwrap().is_file(), "step {fail_at}");
+            assert_eq!(fs::read_to_string(&old).unwrap(), "old", "step {fail_at}");
+            assert!(!dir.path().join("a").exists(), "step {fail_at}");
+            assert_eq!(
+                fs::read_dir(dir.path()).unwrap().count(),
+                2,
+                "step {fail_at}"
+            );
+        }
+    }
+
+    #[test]
+    fn ensure_dir_skips_existing_and_planned_directories() {
+        let dir = TempDir::new().unwrap();
+
+        let mut plan = Plan::new();
+        plan.ensure_dir(dir.path());
+        plan.ensure_dir(&dir.path().join("a/b"));
+        plan.ensure_dir(&dir.path().join("a"));
+        plan.ensure_dir(&dir.path().join("a/c"));
+
+        assert_eq!(
+            plan.actions(),
+            [
+                Action::CreateDir(dir.path().join("a/b")),
+                Action::CreateDir(dir.path().join("a/c")),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_sets_mode_regardless_of_umask() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+
+        let mut plan = Plan::new();
+        plan.push(Action::Write {
+            path: path.clone(),
+            content: b"content".to_vec(),
+            mode: Some(0o666),
+        });
+        plan.begin_with(|_| Ok(())).unwrap().commit().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content");
+        assert_eq!(
+            path.metadata().unwrap().permissions().mode() & 0o7777,
+            0o666
+        );
+    }
+
+    #[test]
+    fn rollback_restores_removed_files() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "content").unwrap();
+
+        let mut plan = Plan::new();
+        plan.push(Action::Remove(file.clone()));
+        let transaction = plan.begin_with(|_| Ok(())).unwrap();
+        assert!(!file.exists());
+
+        transaction.rollback().unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+    }
+
+    #[test]
+    fn commit_deletes_removed_files() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "content").unwrap();
+
+        let mut plan = Plan::new();
+        plan.push(Action::Remove(file.clone()));
+        plan.begin_with(|_| Ok(())).unwrap().commit().unwrap();
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
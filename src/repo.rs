@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::manifest::MANIFEST_FILE;
+
+/// Find the repository root.
+///
+/// An explicit directory (from `--repo` or `DOT_REPO`) must contain a
+/// manifest. Otherwise `start` and its parents are searched for one, the
+/// same way git looks for `.git`.
+pub fn discover(explicit: Option<&Path>, start: &Path) -> Result<PathBuf> {
+    if let Some(dir) = explicit {
+        let dir = start.join(dir);
+        if !dir.join(MANIFEST_FILE).is_file() {
+            return Err(Error::NotARepository(dir));
+        }
+        return Ok(dir.canonicalize()?);
+    }
+
+    start
+        .ancestors()
+        .find(|dir| dir.join(MANIFEST_FILE).is_file())
+        .map(|dir| dir.canonicalize())
+        .transpose()?
+        .ok_or_else(|| Error::NotARepository(start.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_manifest_in_start_directory() {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join(MANIFEST_FILE), "").unwrap();
+
+        let root = discover(None, repo.path()).unwrap();
+
+        assert_eq!(root, repo.path().canonicalize().unwrap());
+    }
+
+    #[test]
+    fn searches_parent_directories() {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join(MANIFEST_FILE), "").unwrap();
+        let nested = repo.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+
+        let root = discover(None, &nested).unwrap();
+
+        assert_eq!(root, repo.path().canonicalize().unwrap());
+    }
+
+    #[test]
+    fn explicit_directory_wins() {
+        let repo = TempDir::new().unwrap();
+        let other = TempDir::new().unwrap();
+        fs::write(repo.path().join(MANIFEST_FILE), "").unwrap();
+        fs::write(other.path().join(MANIFEST_FILE), "").unwrap();
+
+        let root = discover(Some(other.path()), repo.path()).unwrap();
+
+        assert_eq!(root, other.path().canonicalize().unwrap());
+    }
+
+    #[test]
+    fn explicit_directory_must_contain_manifest() {
+        let repo = TempDir::new().unwrap();
+        fs::write(repo.path().join(MANIFEST_FILE), "").unwrap();
+        let empty = TempDir::new().unwrap();
+
+        let result = discover(Some(empty.path()), repo.path());
+
+        assert!(matches!(result, Err(Error::NotARepository(_))));
+    }
+
+    #[test]
+    fn errors_when_no_manifest_found() {
+        let dir = TempDir::new().unwrap();
+        let result = discover(None, dir.path());
+        assert!(matches!(result, Err(Error::NotARepository(_))));
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use age::secrecy::{ExposeSecret, SecretString};
+use age::{scrypt, x25519};
+
+use crate::error::{Error, Result};
+
+/// Environment variable holding the passphrase when no key file is used.
+pub const PASSPHRASE_VAR: &str = "DOT_PASSPHRASE";
+
+/// What encrypted entries are encrypted with: a passphrase, or an age
+/// X25519 identity read from a key file.
+pub enum Key {
+    Passphrase(SecretString),
+    Identity(x25519::Identity),
+}
+
+impl Key {
+    /// The identity in `key_file` if given, otherwise the passphrase from
+    /// `DOT_PASSPHRASE` or, failing that, the terminal.
+    pub fn load(key_file: Option<&Path>) -> Result<Self> {
+        if let Some(path) = key_file {
+            return Self::read_identity(path);
+        }
+        if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+            return Ok(Self::Passphrase(passphrase.into()));
+        }
+        prompt_passphrase().map(Self::Passphrase)
+    }
+
+    fn read_identity(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+            .ok_or_else(|| Error::InvalidKeyFile(path.to_path_buf()))?;
+        line.parse()
+            .map(Self::Identity)
+            .map_err(|_| Error::InvalidKeyFile(path.to_path_buf()))
+    }
+
+    /// Encrypt to ASCII-armored age format, which diffs and merges as text.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let encrypted = match self {
+            Self::Passphrase(passphrase) => {
+                #[allow(unused_mut)]
+                let mut recipient = scrypt::Recipient::new(passphrase.clone());
+                // Keep tests fast; real files use age's default cost
+                #[cfg(test)]
+                recipient.set_work_factor(10);
+                age::encrypt_and_armor(&recipient, plaintext)
+            }
+            Self::Identity(identity) => age::encrypt_and_armor(&identity.to_public(), plaintext),
+        };
+        encrypted.map_err(|e| Error::Encrypt(e.to_string()))
+    }
+
+    /// Decrypt the contents of the repository file `path`.
+    pub fn decrypt_file(&self, path: &Path) -> Result<Vec<u8>> {
+        let ciphertext = fs::read(path)?;
+        let decrypted = match self {
+            Self::Passphrase(passphrase) => {
+                age::decrypt(&scrypt::Identity::new(passphrase.clone()), &ciphertext)
+            }
+            Self::Identity(identity) => age::decrypt(identity, &ciphertext),
+        };
+        decrypted.map_err(|e| Error::Decrypt(path.to_path_buf(), e.to_string()))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("Key::Passphrase(..)"),
+            Self::Identity(_) => f.write_str("Key::Identity(..)"),
+        }
+    }
+}
+
+/// Write a new identity to `path`, readable only by its owner, and return
+/// its public key.
+pub fn generate_key_file(path: &Path) -> Result<String> {
+    let identity = x25519::Identity::generate();
+    let public = identity.to_public().to_string();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    writeln!(file, "# public key: {public}")?;
+    writeln!(file, "{}", identity.to_string().expose_secret())?;
+    Ok(public)
+}
+
+/// Read a passphrase from the terminal without echoing it.
+fn prompt_passphrase() -> Result<SecretString> {
+    let tty = File::open("/dev/tty").map_err(|_| Error::NoKey)?;
+    eprint!("Passphrase: ");
+    let echo = |flag: &str| {
+        Command::new("stty")
+            .arg(flag)
+            .stdin(tty.try_clone()?)
+            .stderr(Stdio::null())
+            .status()
+    };
+
+    echo("-echo")?;
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    echo("echo")?;
+    eprintln!();
+    read?;
+
+    let passphrase = line.trim_end_matches(['\n', '\r']);
+    if passphrase.is_empty() {
+        return Err(Error::NoKey);
+    }
+    Ok(passphrase.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn roundtrip(key: &Key) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("netrc");
+        let ciphertext = key
+            .encrypt(b"machine example.com password hunter2")
+            .unwrap();
+        assert!(ciphertext.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert!(!ciphertext.contains("hunter2"));
+
+        fs::write(&path, &ciphertext).unwrap();
+        assert_eq!(
+            key.decrypt_file(&path).unwrap(),
+            b"machine example.com password hunter2"
+        );
+    }
+
+    #[test]
+    fn roundtrips_with_passphrase() {
+        roundtrip(&Key::Passphrase("correct horse".to_string().into()));
+    }
+
+    #[test]
+    fn roundtrips_with_generated_key_file() {
+        let dir = TempDir::new().unwrap();
+        let key_file = dir.path().join("key.txt");
+        let public = generate_key_file(&key_file).unwrap();
+        assert!(public.starts_with("age1"));
+
+        roundtrip(&Key::load(Some(&key_file)).unwrap());
+    }
+
+    #[test]
+    fn wrong_key_fails_to_decrypt() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("secret");
+        let key = Key::Passphrase("right".to_string().into());
+        fs::write(&path, key.encrypt(b"secret").unwrap()).unwrap();
+
+        let wrong = Key::Passphrase("wrong".to_string().into());
+        assert!(matches!(wrong.decrypt_file(&path), Err(Error::Decrypt(..))));
+    }
+
+    #[test]
+    fn rejects_key_file_without_identity() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("key.txt");
+        fs::write(&path, "# nothing here\n").unwrap();
+        assert!(matches!(
+            Key::load(Some(&path)),
+            Err(Error::InvalidKeyFile(_))
+        ));
+    }
+}
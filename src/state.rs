@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::entry::files_under;
+use crate::error::{Error, Result};
+use crate::transfer::write_atomic;
+
+/// Machine-local record of what was deployed, kept next to the manifest
+/// but not meant to be committed; `dot init` adds it to `.gitignore`.
+pub const STATE_FILE: &str = ".dot-state.toml";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// Content hash of each copied target when it was last deployed
+    #[serde(default)]
+    copies: BTreeMap<PathBuf, String>,
+    /// Content hash of the repository file each encrypted target was
+    /// decrypted from, so status can tell without decrypting
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<PathBuf, String>,
+    /// Symlinks created by `dot`, so they can be pruned once their entry
+    /// leaves the manifest
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    links: BTreeSet<PathBuf>,
+}
+
+impl State {
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).map_err(Error::TomlSerialize)?;
+        write_atomic(path, &content)
+    }
+
+    /// Hash recorded for a copied or rendered target.
+    pub fn copy_hash(&self, target: &Path) -> Option<&str> {
+        self.copies.get(target).map(String::as_str)
+    }
+
+    pub fn record_copy(&mut self, target: PathBuf, hash: String) {
+        self.copies.insert(target, hash);
+    }
+
+    /// Hash of the repository file an encrypted target was deployed from.
+    pub fn source_hash(&self, target: &Path) -> Option<&str> {
+        self.sources.get(target).map(String::as_str)
+    }
+
+    pub fn record_source(&mut self, target: PathBuf, hash: String) {
+        self.sources.insert(target, hash);
+    }
+
+    pub fn links(&self) -> &BTreeSet<PathBuf> {
+        &self.links
+    }
+
+    pub fn record_link(&mut self, link: PathBuf) {
+        self.links.insert(link);
+    }
+
+    pub fn forget_link(&mut self, link: &Path) {
+        self.links.remove(link);
+    }
+
+    /// Drop everything recorded at or below `target`, once it is no longer
+    /// deployed.
+    pub fn forget(&mut self, target: &Path) {
+        self.copies.retain(|path, _| !path.starts_with(target));
+        self.sources.retain(|path, _| !path.starts_with(target));
+        self.links.retain(|path| !path.starts_with(target));
+    }
+
+    /// Move everything recorded at or below `from` to the same place below
+    /// `to`, after a deployed target was moved there.
+    pub fn relocate(&mut self, from: &Path, to: &Path) {
+        let moved = |path: &PathBuf| match path.strip_prefix(from) {
+            Ok(rest) if rest.as_os_str().is_empty() => to.to_path_buf(),
+            Ok(rest) => to.join(rest),
+            Err(_) => path.clone(),
+        };
+        self.copies = self
+            .copies
+            .iter()
+            .map(|(p, h)| (moved(p), h.clone()))
+            .collect();
+        self.sources = self
+            .sources
+            .iter()
+            .map(|(p, h)| (moved(p), h.clone()))
+            .collect();
+        self.links = self.links.iter().map(moved).collect();
+    }
+}
+
+/// SHA-256 of a file, or of every file below a directory together with
+/// their relative paths, as lowercase hex.
+pub fn content_hash(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+
+    if path.is_dir() {
+        for relative in files_under(path)? {
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+            hasher.update([0]);
+            hash_file(&path.join(&relative), &mut hasher)?;
+        }
+    } else {
+        hash_file(path, &mut hasher)?;
+    }
+
+    Ok(hex(hasher))
+}
+
+/// SHA-256 of in-memory content, matching `content_hash` of a file with
+/// the same bytes.
+pub fn bytes_hash(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content);
+    hex(hasher)
+}
+
+fn hex(hasher: Sha256) -> String {
+    let mut hex = String::new();
+    for byte in hasher.finalize() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn hash_file(path: &Path, hasher: &mut Sha256) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = [0; 8192];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn hashes_file_contents() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "abc").unwrap();
+
+        assert_eq!(
+            content_hash(&file).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn directory_hash_covers_names_and_contents() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a"), "1").unwrap();
+        let before = content_hash(dir.path()).unwrap();
+
+        fs::rename(dir.path().join("a"), dir.path().join("b")).unwrap();
+        let renamed = content_hash(dir.path()).unwrap();
+        fs::write(dir.path().join("b"), "2").unwrap();
+        let edited = content_hash(dir.path()).unwrap();
+
+        assert_ne!(before, renamed);
+        assert_ne!(renamed, edited);
+    }
+
+    #[test]
+    fn roundtrips_through_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(STATE_FILE);
+        assert_eq!(State::load_from(&path).unwrap(), State::default());
+
+        let mut state = State::default();
+        state.record_copy("/home/user/.ssh/config".into(), "abc".into());
+        state.save_to(&path).unwrap();
+
+        let loaded = State::load_from(&path).unwrap();
+        assert_eq!(
+            loaded.copy_hash(Path::new("/home/user/.ssh/config")),
+            Some("abc")
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::facts::Facts;
+
+/// Values that `{{ name }}` placeholders in templates are replaced with.
+///
+/// `{{ env.NAME }}` reads an environment variable; other names come from
+/// the manifest's `[vars]` or the built-in `hostname`, `user` and `os`.
+#[derive(Debug)]
+pub struct Vars<'a> {
+    values: BTreeMap<String, String>,
+    facts: &'a dyn Facts,
+}
+
+impl<'a> Vars<'a> {
+    /// Built-in variables from `facts`, overridden by `custom`.
+    pub fn new(facts: &'a dyn Facts, custom: &BTreeMap<String, String>) -> Self {
+        let mut values = BTreeMap::new();
+        values.insert("os".to_string(), facts.os().to_string());
+        if let Some(hostname) = facts.hostname() {
+            values.insert("hostname".to_string(), hostname.to_string());
+        }
+        if let Some(user) = facts.env("USER").or_else(|| facts.env("LOGNAME")) {
+            values.insert("user".to_string(), user);
+        }
+        values.extend(custom.clone());
+        Self { values, facts }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        match name.strip_prefix("env.") {
+            Some(var) => self.facts.env(var),
+            None => self.values.get(name).cloned(),
+        }
+    }
+}
+
+/// Render the template at `path`, naming it in any error.
+pub fn render_file(path: &Path, vars: &Vars) -> Result<String> {
+    let source = fs::read_to_string(path)?;
+    render(&source, vars).map_err(|message| Error::Template(path.to_path_buf(), message))
+}
+
+/// Replace every `{{ name }}` in `source`, and every `{{ "text" }}` with
+/// the quoted text, so `{{ "{{" }}` writes a literal `{{`. Undefined
+/// variables and unclosed placeholders are errors rather than rendering as
+/// blanks.
+fn render(source: &str, vars: &Vars) -> std::result::Result<String, String> {
+    let mut output = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unclosed {{{{ at line {}", line_of(source, rest, start)))?;
+        let name = after[..end].trim();
+        match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            Some(literal) => output.push_str(literal),
+            None => {
+                let value = vars
+                    .get(name)
+                    .ok_or_else(|| format!("undefined variable {name:?}"))?;
+                output.push_str(&value);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// 1-based line number of `offset` within `rest`, a suffix of `source`.
+fn line_of(source: &str, rest: &str, offset: usize) -> usize {
+    let position = source.len() - rest.len() + offset;
+    source[..position].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::facts::FakeFacts;
+
+    fn facts() -> FakeFacts {
+        FakeFacts {
+            os: "linux".into(),
+            hostname: Some("work-42".into()),
+            env: [
+                ("USER".into(), "alice".into()),
+                ("EDITOR".into(), "nvim".into()),
+            ]
+            .into(),
+            ..FakeFacts::default()
+        }
+    }
+
+    #[test]
+    fn renders_builtin_custom_and_env_variables() {
+        let facts = facts();
+        let custom = [("email".to_string(), "alice@work.example".to_string())].into();
+        let vars = Vars::new(&facts, &custom);
+
+        let rendered = render(
+            "[user]\n  name = {{user}}\n  email = {{ email }}\n# {{ hostname }} {{os}} {{ env.EDITOR }}\n",
+            &vars,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rendered,
+            "[user]\n  name = alice\n  email = alice@work.example\n# work-42 linux nvim\n"
+        );
+    }
+
+    #[test]
+    fn custom_variables_override_builtins() {
+        let facts = facts();
+        let custom = [("hostname".to_string(), "laptop".to_string())].into();
+        assert_eq!(
+            render("{{ hostname }}", &Vars::new(&facts, &custom)).unwrap(),
+            "laptop"
+        );
+    }
+
+    #[test]
+    fn quoted_text_escapes_braces() {
+        let facts = facts();
+        let vars = Vars::new(&facts, &BTreeMap::new());
+        assert_eq!(
+            render(
+                "[alias]\n  fmt = !go run {{ \"{{\" }} .Name }} {{ user }}\n",
+                &vars
+            )
+            .unwrap(),
+            "[alias]\n  fmt = !go run {{ .Name }} alice\n"
+        );
+    }
+
+    #[test]
+    fn undefined_and_unclosed_placeholders_error() {
+        let facts = facts();
+        let vars = Vars::new(&facts, &BTreeMap::new());
+        assert!(render("{{ signing_key }}", &vars).is_err());
+        assert!(render("{{ env.MISSING }}", &vars).is_err());
+        assert_eq!(
+            render("ok\n{{ user", &vars).unwrap_err(),
+            "unclosed {{ at line 2"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by unit tests.
+
+use std::fs;
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+
+use crate::context::Context;
+
+/// Test fixture: an empty repo and a fake home directory. Test modules add
+/// their own setup in further `impl Fixture` blocks.
+pub struct Fixture {
+    pub repo: TempDir,
+    pub home: TempDir,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self {
+            repo: TempDir::new().unwrap(),
+            home: TempDir::new().unwrap(),
+        }
+    }
+
+    /// Create a file under the fake home and return its path
+    pub fn source(&self, relative: &str) -> PathBuf {
+        let path = self.home.path().join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "content").unwrap();
+        path
+    }
+
+    /// A context for the repo that expands `~` to the fake home
+    pub fn ctx(&self) -> Context {
+        let mut ctx = Context::new(self.repo.path());
+        ctx.home = Some(self.home.path().to_path_buf());
+        ctx
+    }
+}
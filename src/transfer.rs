@@ -0,0 +1,396 @@
+use std::ffi::OsString;
+use std::fs::{self, File, FileTimes, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use crate::conflict::remove_path;
+use crate::error::Result;
+
+/// Move a file or directory, falling back to copy-then-delete when `from`
+/// and `to` live on different filesystems.
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => move_by_copy(from, to),
+        result => Ok(result?),
+    }
+}
+
+/// Copy `from` to `to` preserving permissions and timestamps, check that the
+/// copy matches, and only then delete `from`.
+///
+/// Either the move completes or `from` is left as the only copy: if
+/// deleting it fails partway through a directory, the deleted parts are
+/// copied back and `to` is removed. Should copying back fail too, `to` is
+/// kept so that nothing is lost.
+pub fn move_by_copy(from: &Path, to: &Path) -> Result<()> {
+    move_by_copy_with(from, to, remove_path)
+}
+
+/// Like `move_by_copy`, deleting the source with `remove`.
+fn move_by_copy_with(
+    from: &Path,
+    to: &Path,
+    remove: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    copy_path(from, to)?;
+    if let Err(e) = remove(from) {
+        // If the source cannot be made whole again, keep the copy rather
+        // than lose what was already deleted
+        if restore_missing(to, from).is_ok() {
+            let _ = remove_path(to);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Copy everything below `copy` that no longer exists below `original`
+/// back into place.
+fn restore_missing(copy: &Path, original: &Path) -> Result<()> {
+    if original.symlink_metadata().is_err() {
+        return copy_path(copy, original);
+    }
+    if copy.symlink_metadata()?.is_dir() && !original.symlink_metadata()?.is_symlink() {
+        for entry in fs::read_dir(copy)? {
+            let entry = entry?;
+            restore_missing(&entry.path(), &original.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy a file or directory tree to a new location, preserving permissions
+/// and timestamps. A partial copy is removed if anything fails.
+pub fn copy_path(from: &Path, to: &Path) -> Result<()> {
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::from(io::ErrorKind::AlreadyExists).into());
+    }
+
+    let copied = copy_tree(from, to).and_then(|_| verify_tree(from, to));
+    if let Err(e) = copied {
+        // Leave the source untouched and clean up the partial copy
+        if to.symlink_metadata().is_ok() {
+            let _ = remove_path(to);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replace `path` with `content` by writing a sibling file and renaming it,
+/// so a failed write never leaves a truncated file behind.
+///
+/// A symlink at `path` is followed and the file it points to replaced, and
+/// an existing file keeps its permissions.
+pub fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    let path = if path.symlink_metadata().is_ok_and(|m| m.is_symlink()) {
+        fs::canonicalize(path)?
+    } else {
+        path.to_path_buf()
+    };
+    let (tmp, mut file) = create_sibling(&path)?;
+
+    let written = (|| {
+        file.write_all(content.as_ref())?;
+        if let Ok(existing) = path.metadata() {
+            file.set_permissions(existing.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Create a new file next to `path` under a name no one else is using.
+fn create_sibling(path: &Path) -> Result<(PathBuf, File)> {
+    let mut counter = 0;
+    loop {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".dot-tmp-{}", std::process::id()));
+        if counter > 0 {
+            name.push(format!(".{counter}"));
+        }
+
+        let candidate = path.with_file_name(name);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let metadata = from.symlink_metadata()?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        symlink(fs::read_link(from)?, to)?;
+    } else if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        // Set attributes last: adding entries updates the directory's
+        // mtime and a read-only mode would block the copy
+        copy_attributes(&metadata, to)?;
+    } else if file_type.is_file() {
+        fs::copy(from, to)?;
+        copy_attributes(&metadata, to)?;
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("cannot copy special file {}", from.display()),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn copy_attributes(metadata: &Metadata, to: &Path) -> Result<()> {
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::open(to)?.set_times(times)?;
+    fs::set_permissions(to, metadata.permissions())?;
+    Ok(())
+}
+
+/// Check that `to` is a faithful copy of `from`.
+fn verify_tree(from: &Path, to: &Path) -> Result<()> {
+    let source = from.symlink_metadata()?;
+    let copy = to.symlink_metadata()?;
+
+    let matches = if source.file_type().is_symlink() {
+        copy.file_type().is_symlink() && fs::read_link(from)? == fs::read_link(to)?
+    } else if source.is_dir() {
+        copy.is_dir() && {
+            let mut count = 0;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                verify_tree(&entry.path(), &to.join(entry.file_name()))?;
+                count += 1;
+            }
+            count == fs::read_dir(to)?.count()
+        }
+    } else {
+        copy.is_file() && source.len() == copy.len() && same_contents(from, to)?
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "copy of {} does not match the original",
+            from.display()
+        ))
+        .into())
+    }
+}
+
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let mut a = File::open(a)?;
+    let mut b = File::open(b)?;
+    let mut buf_a = [0; 8192];
+    let mut buf_b = [0; 8192];
+
+    loop {
+        let read = a.read(&mut buf_a)?;
+        if read == 0 {
+            return Ok(b.read(&mut buf_b)? == 0);
+        }
+        b.read_exact(&mut buf_b[..read])?;
+        if buf_a[..read] != buf_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    #[test]
+    fn copies_file_with_permissions_and_mtime() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::write(&from, "content").unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o600)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(&from)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        move_by_copy(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        let metadata = to.metadata().unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "content");
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+    }
+
+    #[test]
+    fn copies_directory_tree() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("nested/file"), "content").unwrap();
+        symlink("nested/file", from.join("link")).unwrap();
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o700)).unwrap();
+
+        move_by_copy(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(
+            fs::read_to_string(to.join("nested/file")).unwrap(),
+            "content"
+        );
+        assert_eq!(
+            fs::read_link(to.join("link")).unwrap(),
+            Path::new("nested/file")
+        );
+        let mode = to.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[test]
+    fn refuses_to_replace_existing_destination() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::write(&from, "content").unwrap();
+        fs::write(&to, "existing").unwrap();
+
+        assert!(move_by_copy(&from, &to).is_err());
+        assert_eq!(fs::read_to_string(&from).unwrap(), "content");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "existing");
+    }
+
+    #[test]
+    fn keeps_source_when_copy_fails() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        fs::write(&from, "content").unwrap();
+
+        let result = move_by_copy(&from, &dir.path().join("missing/to"));
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&from).unwrap(), "content");
+    }
+
+    #[test]
+    fn keeps_only_source_when_removing_it_fails() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::create_dir_all(from.join("a")).unwrap();
+        fs::create_dir_all(from.join("b")).unwrap();
+        fs::write(from.join("a/file"), "a").unwrap();
+        fs::write(from.join("b/file"), "b").unwrap();
+
+        // Delete part of the tree, then fail like a permission error would
+        let result = move_by_copy_with(&from, &to, |from| {
+            fs::remove_dir_all(from.join("a"))?;
+            fs::remove_file(from.join("b/file"))?;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied).into())
+        });
+
+        assert!(result.is_err());
+        assert!(to.symlink_metadata().is_err());
+        assert_eq!(fs::read_to_string(from.join("a/file")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(from.join("b/file")).unwrap(), "b");
+    }
+
+    #[test]
+    fn write_atomic_keeps_permissions_and_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let real = dir.path().join("real");
+        let link = dir.path().join("link");
+        fs::write(&real, "old").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&real, &link).unwrap();
+
+        write_atomic(&link, "new").unwrap();
+
+        assert!(link.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new");
+        assert_eq!(
+            real.metadata().unwrap().permissions().mode() & 0o7777,
+            0o600
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn write_atomic_leaves_other_temporary_files_alone() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dot.toml");
+        let stale = dir
+            .path()
+            .join(format!(".dot.toml.dot-tmp-{}", std::process::id()));
+        fs::write(&stale, "someone else's").unwrap();
+
+        write_atomic(&path, "content").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content");
+        assert_eq!(fs::read_to_string(&stale).unwrap(), "someone else's");
+    }
+
+    #[test]
+    fn move_path_renames_on_same_filesystem() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+        fs::write(&from, "content").unwrap();
+
+        move_path(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "content");
+    }
+
+    #[test]
+    fn move_path_crosses_filesystems() {
+        use std::os::unix::fs::MetadataExt;
+
+        // Only meaningful where a tmpfs is mounted separately from the temp dir
+        let Ok(other) = TempDir::new_in("/dev/shm") else {
+            return;
+        };
+        let dir = TempDir::new().unwrap();
+        if dir.path().metadata().unwrap().dev() == other.path().metadata().unwrap().dev() {
+            return;
+        }
+
+        let from = dir.path().join("from");
+        let to = other.path().join("to");
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("file"), "content").unwrap();
+
+        move_path(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "content");
+    }
+}